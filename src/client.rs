@@ -0,0 +1,126 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+};
+
+use serde_json::{json, Value};
+
+use crate::control::Status;
+
+/// A connection to the control socket of a running `awc watch`
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    pub fn connect(path: &str) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    /// Sends one request and returns its `result`, or the daemon's error
+    pub fn request(&mut self, request: Value) -> Result<Value, String> {
+        writeln!(self.writer, "{request}").map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        self.reader
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?;
+        let mut response: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+        if response["ok"] == true {
+            Ok(response["result"].take())
+        } else {
+            Err(response["error"]
+                .as_str()
+                .unwrap_or("no answer")
+                .to_string())
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Status, String> {
+        let result = self.request(json!({ "cmd": "status" }))?;
+        serde_json::from_value(result).map_err(|e| e.to_string())
+    }
+}
+
+pub fn show_all_info(client: &mut Client) -> Result<(), String> {
+    print!("{}", client.status()?);
+    Ok(())
+}
+
+pub fn show_temps(client: &mut Client) -> Result<(), String> {
+    for fan in client.status()?.fans {
+        println!("Sensor {} #{}: {}", fan.name, fan.sensor_id, fan.temp);
+    }
+    Ok(())
+}
+
+pub fn show_fan_boosts(client: &mut Client) -> Result<(), String> {
+    for fan in client.status()?.fans {
+        println!(
+            "Fan #{}:\n boost: {}/255, rpm: {}",
+            fan.fan_id, fan.boost, fan.rpm
+        );
+    }
+    Ok(())
+}
+
+/// Pins both fans on the daemon side, so its next tick doesn't undo it
+pub fn set_both_fan_boosts(
+    client: &mut Client,
+    boost: u8,
+    seconds: Option<u64>,
+) -> Result<(), String> {
+    for fan in client.status()?.fans {
+        let result = client.request(json!({
+            "cmd": "boost",
+            "fan": fan.fan_id,
+            "boost": boost,
+            "seconds": seconds,
+        }))?;
+        println!("{}", result.as_str().unwrap_or_default());
+    }
+    Ok(())
+}
+
+pub fn clear_fan_boosts(client: &mut Client) -> Result<(), String> {
+    let result = client.request(json!({ "cmd": "auto" }))?;
+    println!("{}", result.as_str().unwrap_or_default());
+    Ok(())
+}
+
+pub fn toggle_power_mode(client: &mut Client) -> Result<(), String> {
+    let result = client.request(json!({ "cmd": "power_mode" }))?;
+    println!("{}", result.as_str().unwrap_or_default());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulated::{drive_socket, test_controller, with_simulated};
+
+    #[test]
+    fn client_commands_go_through_the_daemon() {
+        with_simulated(|sim| {
+            let (pinned, cleared, power_mode) = drive_socket(&mut test_controller(), |path| {
+                let mut daemon = Client::connect(path).unwrap();
+                show_all_info(&mut daemon).unwrap();
+                set_both_fan_boosts(&mut daemon, 200, None).unwrap();
+                let pinned = daemon.status().unwrap();
+                clear_fan_boosts(&mut daemon).unwrap();
+                let cleared = daemon.status().unwrap();
+                toggle_power_mode(&mut daemon).unwrap();
+                (pinned, cleared, sim.power_mode())
+            });
+
+            let pins: Vec<_> = pinned.fans.iter().map(|fan| fan.pinned).collect();
+            assert_eq!(pins, [Some(200), Some(200)]);
+            assert_eq!((pinned.fans[0].boost, pinned.fans[1].boost), (200, 200));
+            assert!(cleared.fans.iter().all(|fan| fan.pinned.is_none()));
+            assert_eq!(cleared.fans[0].boost, 133);
+            assert_eq!(power_mode, 0xab);
+        });
+    }
+}
@@ -1,17 +1,33 @@
-use std::{fs::OpenOptions, io::Read, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use serde::Deserialize;
+use serde_json::Value;
 
-use crate::controller::CoOrdinates;
+use crate::{
+    controller::{get_coords_from_string, line_to_coords, CoOrdinates, Probes},
+    GraphType,
+};
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(rename_all = "lowercase")]
-pub enum GraphType {
-    Linear,
-    Step,
+const SYSTEM_CONFIG_PATH: &str = "/etc/awc.conf";
+/// `*.conf` fragments in here are merged over the config, in lexical order
+const DROP_IN_DIR: &str = "/etc/awc.d";
+/// Where `config init` writes to, the user's file in the lookup order
+pub fn user_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("awc").join("awc.conf"))
 }
 
+/// Slower than this and a heating CPU is noticed too late
+const MAX_INTERVAL: u64 = 600;
+/// No sensor reads hotter, a graph point past it is a typo
+const MAX_TEMP: u8 = 110;
+
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AwcConfig {
     pub disable_power_mode_on_startup: bool,
     pub cpu: DeviceInfo,
@@ -20,6 +36,7 @@ pub struct AwcConfig {
 }
 
 #[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct DeviceInfo {
     pub graph_type: GraphType,
     pub graph: Vec<CoOrdinates>,
@@ -27,27 +44,953 @@ pub struct DeviceInfo {
     pub fan: u8,
 }
 
+/// A single config file, which like a drop-in may only set some of the keys
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Layer {
+    disable_power_mode_on_startup: Option<bool>,
+    cpu: Option<DeviceLayer>,
+    gpu: Option<DeviceLayer>,
+    interval: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceLayer {
+    graph_type: Option<GraphType>,
+    graph: Option<Vec<CoOrdinates>>,
+    sensor: Option<u8>,
+    fan: Option<u8>,
+}
+
 impl AwcConfig {
-    pub fn from_file_path(file_path: &str) -> Self {
-        let mut s = String::with_capacity(1024);
-        OpenOptions::new()
-            .read(true)
-            .open(file_path)
-            .unwrap()
-            .read_to_string(&mut s);
-        json5::from_str(&s).unwrap()
+    /// The config as json5, with a comment on what every key does
+    pub fn to_json5(&self) -> String {
+        let device = |name: &str, device: &DeviceInfo| {
+            let graph: Vec<_> = device
+                .graph
+                .iter()
+                .map(|point| format!("{{ temp: {}, fan_boost: {} }}", point.temp, point.fan_boost))
+                .collect();
+            format!(
+                "  {name}: {{
+    // \"linear\" interpolates between the points, \"step\" holds the next point's boost
+    graph_type: {},
+    // °C to raw boost (0-255), temperatures and boosts have to go up
+    graph: [{}],
+    sensor: {},
+    fan: {},
+  }},
+",
+                serde_json::to_string(&device.graph_type).unwrap(),
+                graph.join(", "),
+                device.sensor,
+                device.fan
+            )
+        };
+        let mut s = String::from("{\n");
+        s += "  // turn the firmware's power mode off when awc starts\n";
+        s += &format!(
+            "  disable_power_mode_on_startup: {},\n",
+            self.disable_power_mode_on_startup
+        );
+        s += "  // seconds between ticks\n";
+        s += &format!("  interval: {},\n", self.interval);
+        s += &device("cpu", &self.cpu);
+        s += &device("gpu", &self.gpu);
+        s += "}\n";
+        s
+    }
+
+    /// `(sensor, fan)` ids of the CPU and GPU
+    pub fn devices(&self) -> ((u8, u8), (u8, u8)) {
+        (
+            (self.cpu.sensor, self.cpu.fan),
+            (self.gpu.sensor, self.gpu.fan),
+        )
+    }
+}
+
+/// Every key an env var or `--set` can override, arrays like graphs are
+/// replaced as a whole
+const KEYS: [&str; 10] = [
+    "disable_power_mode_on_startup",
+    "interval",
+    "cpu.graph_type",
+    "cpu.graph",
+    "cpu.sensor",
+    "cpu.fan",
+    "gpu.graph_type",
+    "gpu.graph",
+    "gpu.sensor",
+    "gpu.fan",
+];
+
+/// A `--set KEY=VALUE` flag
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if KEYS.contains(&key) => Ok(Self {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            Some((key, _)) => Err(format!(
+                "unknown key {key}, expected one of {}",
+                KEYS.join(", ")
+            )),
+            None => Err(format!("expected KEY=VALUE, got {s}")),
+        }
+    }
+}
+
+/// `AWC_CPU_GRAPH_TYPE` for `cpu.graph_type`
+fn env_var(key: &str) -> String {
+    format!("AWC_{}", key.to_uppercase().replace('.', "_"))
+}
+
+/// `(var, key, value)` of every `AWC_<KEY>` env var that is set
+pub fn env_overrides() -> Vec<(String, &'static str, String)> {
+    KEYS.iter()
+        .filter_map(|key| {
+            let var = env_var(key);
+            let value = env::var(&var).ok()?;
+            Some((var, *key, value))
+        })
+        .collect()
+}
+
+/// The files and `--set` flags a config is loaded from, kept to load it again
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub files: Vec<PathBuf>,
+    pub sets: Vec<Override>,
+}
+
+impl ConfigSource {
+    /// Whether there is no config file. Overrides only change a config,
+    /// without a file they are ignored.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Whether any `--set` or `AWC_<KEY>` env var is given
+    pub fn has_overrides(&self) -> bool {
+        !self.sets.is_empty() || !env_overrides().is_empty()
+    }
+
+    pub fn effective(&self) -> Result<Effective, String> {
+        Effective::load(&self.files, &self.sets)
+    }
+
+    /// The merged config, refused when it has values no curve can run with
+    pub fn load(&self) -> Result<AwcConfig, String> {
+        let effective = self.effective()?;
+        let config = effective.config()?;
+        let problems: Vec<String> = validate(&config)
+            .into_iter()
+            .map(|(key, message)| match effective.sources.get(&key) {
+                Some(source) => format!("{source}: {message}"),
+                None => message,
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files: Vec<_> = self.files.iter().map(|p| p.display().to_string()).collect();
+        write!(f, "{}", files.join(" + "))?;
+        if self.has_overrides() {
+            write!(f, " with overrides")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a key's value comes from
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    File(PathBuf),
+    /// An `AWC_<KEY>` env var, by name
+    Env(String),
+    Set,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "${var}"),
+            Source::Set => write!(f, "--set"),
+        }
+    }
+}
+
+/// The config files that apply, in the order they are merged: the first of
+/// `explicit` (`--config`), `$AWC_CONFIG`, `$XDG_CONFIG_HOME/awc/awc.conf` and
+/// `/etc/awc.conf`, then the drop-ins in `/etc/awc.d`. Empty without any.
+pub fn lookup(explicit: Option<&Path>) -> Result<Vec<PathBuf>, String> {
+    let explicit = explicit
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os("AWC_CONFIG").map(PathBuf::from));
+    lookup_in(
+        explicit,
+        user_path(),
+        Path::new(SYSTEM_CONFIG_PATH),
+        Path::new(DROP_IN_DIR),
+    )
+}
+
+fn lookup_in(
+    explicit: Option<PathBuf>,
+    user: Option<PathBuf>,
+    system: &Path,
+    drop_in_dir: &Path,
+) -> Result<Vec<PathBuf>, String> {
+    let base = match explicit {
+        // asked for by name, so it has to be there
+        Some(path) if !path.exists() => return Err(format!("{}: not found", path.display())),
+        Some(path) => Some(path),
+        None => user
+            .into_iter()
+            .chain([system.to_path_buf()])
+            .find(|path| path.exists()),
+    };
+
+    let mut drop_ins = match fs::read_dir(drop_in_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect(),
+        Err(_) => vec![],
+    };
+    drop_ins.sort();
+    Ok(base.into_iter().chain(drop_ins).collect())
+}
+
+/// The merged config, with where every key was last set. Files are merged
+/// in order, then env vars override them and `--set` flags override both.
+#[derive(Debug, Clone, Default)]
+pub struct Effective {
+    pub value: Value,
+    /// Dotted key path to what set it
+    pub sources: BTreeMap<String, Source>,
+}
+
+impl Effective {
+    pub fn load(files: &[PathBuf], sets: &[Override]) -> Result<Self, String> {
+        let mut effective = Self::merge(files)?;
+        for (var, key, value) in env_overrides() {
+            effective.set(key, &value, Source::Env(var));
+        }
+        for set in sets {
+            effective.set(&set.key, &set.value, Source::Set);
+        }
+        Ok(effective)
+    }
+
+    pub fn merge(files: &[PathBuf]) -> Result<Self, String> {
+        let mut effective = Effective {
+            value: Value::Object(Default::default()),
+            sources: BTreeMap::new(),
+        };
+        for path in files {
+            let s = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            let layer: Value =
+                json5::from_str(&s).map_err(|e| format!("{}: {e}", path.display()))?;
+            if !layer.is_object() {
+                return Err(format!("{}: expected an object", path.display()));
+            }
+            merge_value(
+                &mut effective.value,
+                layer,
+                "",
+                &Source::File(path.clone()),
+                &mut effective.sources,
+            );
+        }
+        Ok(effective)
+    }
+
+    pub fn config(&self) -> Result<AwcConfig, String> {
+        AwcConfig::deserialize(&self.value).map_err(|e| {
+            let mut sources: Vec<_> = self.sources.values().collect();
+            sources.sort();
+            sources.dedup();
+            let sources: Vec<_> = sources.iter().map(ToString::to_string).collect();
+            match sources.is_empty() {
+                true => format!("config: {e}"),
+                false => format!("{}: {e}", sources.join(" + ")),
+            }
+        })
+    }
+
+    /// Sets the dotted `key`, one of [`KEYS`]. Values are read as json5 so
+    /// numbers, booleans and graphs work, anything else is taken as a string.
+    fn set(&mut self, key: &str, value: &str, source: Source) {
+        let value = json5::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        let mut slot = &mut self.value;
+        for name in key.split('.') {
+            if !slot.is_object() {
+                *slot = Value::Object(Default::default());
+            }
+            slot = slot
+                .as_object_mut()
+                .unwrap()
+                .entry(name)
+                .or_insert(Value::Null);
+        }
+        *slot = value;
+        self.sources.insert(key.to_string(), source);
+    }
+}
+
+/// One thing wrong with the config, pointing into the file that caused it
+#[derive(Debug)]
+pub struct Problem {
+    pub path: Option<PathBuf>,
+    /// 1-based line and column
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+            if let Some((line, column)) = self.location {
+                write!(f, "{line}:{column}:")?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Checks every file on its own for syntax, types and unknown keys, then the
+/// merged config for sane values and, with `probes`, ids this machine has
+pub fn check(files: &[PathBuf], sets: &[Override], probes: Option<&Probes>) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut texts = BTreeMap::new();
+    for path in files {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                problems.push(Problem {
+                    path: Some(path.clone()),
+                    location: None,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if let Err(json5::Error::Message { msg, location }) = json5::from_str::<Layer>(&text) {
+            // syntax errors come with a drawing of the line, the location says it already
+            let message = match msg.rsplit_once("\n  = ") {
+                Some((_, message)) => message.to_string(),
+                None => msg,
+            };
+            problems.push(Problem {
+                path: Some(path.clone()),
+                location: location.map(|l| (l.line, l.column)),
+                message,
+            });
+        }
+        texts.insert(path, text);
+    }
+    if !problems.is_empty() {
+        return problems;
+    }
+
+    let effective = match Effective::load(files, sets) {
+        Ok(effective) => effective,
+        Err(message) => {
+            return vec![Problem {
+                path: None,
+                location: None,
+                message,
+            }]
+        }
+    };
+    let config = match effective.config() {
+        Ok(config) => config,
+        Err(message) => {
+            return vec![Problem {
+                path: None,
+                location: None,
+                message,
+            }]
+        }
+    };
+
+    // blame the file that set `key`, at the key itself, or the override
+    let mut report = |key: &str, message: String| {
+        let problem = match effective.sources.get(key) {
+            Some(Source::File(path)) => Problem {
+                location: texts.get(path).and_then(|text| locate(text, key)),
+                path: Some(path.clone()),
+                message,
+            },
+            Some(source) => Problem {
+                path: None,
+                location: None,
+                message: format!("{source}: {message}"),
+            },
+            None => Problem {
+                path: None,
+                location: None,
+                message,
+            },
+        };
+        problems.push(problem);
+    };
+
+    for (key, message) in validate(&config) {
+        report(&key, message);
     }
+    for (name, device) in [("cpu", &config.cpu), ("gpu", &config.gpu)] {
+        if let Some(probes) = probes {
+            if !probes.fans.iter().any(|(fan_id, _)| *fan_id == device.fan) {
+                let fans: Vec<_> = probes.fans.iter().map(|(id, _)| id.to_string()).collect();
+                report(
+                    &format!("{name}.fan"),
+                    format!(
+                        "{name}.fan {} is not a fan of this machine, it has {}",
+                        device.fan,
+                        fans.join(", ")
+                    ),
+                );
+            }
+            if !probes.sensors.contains(&device.sensor) {
+                let sensors: Vec<_> = probes.sensors.iter().map(u8::to_string).collect();
+                report(
+                    &format!("{name}.sensor"),
+                    format!(
+                        "{name}.sensor {} is not a sensor of this machine, it has {}",
+                        device.sensor,
+                        sensors.join(", ")
+                    ),
+                );
+            }
+        }
+    }
+    problems
+}
 
-    pub fn new() -> Self {
-        if let Some(dir) = dirs::config_dir() {
-            print!("Config Dir {}\n", dir.to_str().unwrap());
-            let config_file_path = "/etc/awc.conf";
-            if PathBuf::from(config_file_path).exists() {
-                print!("Using config file from {config_file_path}\n");
-                return Self::from_file_path(&config_file_path);
+/// Values the controller can't run with, as `(key, message)`
+fn validate(config: &AwcConfig) -> Vec<(String, String)> {
+    let mut problems = vec![];
+    if let Err(e) = check_interval(config.interval) {
+        problems.push((String::from("interval"), e));
+    }
+    for (name, device) in [("cpu", &config.cpu), ("gpu", &config.gpu)] {
+        let graph = format!("{name}.graph");
+        for message in check_graph(&graph, &device.graph) {
+            problems.push((graph.clone(), message));
+        }
+    }
+    problems
+}
+
+/// The tick interval has to be a wait the controller can sleep for
+pub fn check_interval(interval: u64) -> Result<(), String> {
+    if (1..=MAX_INTERVAL).contains(&interval) {
+        Ok(())
+    } else {
+        Err(format!(
+            "interval has to be 1 to {MAX_INTERVAL} seconds, not {interval}"
+        ))
+    }
+}
+
+/// Why `graph` can't be a curve: no points, temperatures past `MAX_TEMP` or
+/// not going up, or boosts going down
+pub fn check_graph(name: &str, graph: &[CoOrdinates]) -> Vec<String> {
+    let mut problems = vec![];
+    if graph.is_empty() {
+        problems.push(format!("{name} has no points"));
+    }
+    if let Some(point) = graph.iter().find(|point| point.temp > MAX_TEMP) {
+        problems.push(format!(
+            "{name} goes up to {}°C, past {MAX_TEMP}°C",
+            point.temp
+        ));
+    }
+    for pair in graph.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if b.temp <= a.temp {
+            problems.push(format!(
+                "{name} temperatures have to go up, {} is followed by {}",
+                a.temp, b.temp
+            ));
+        } else if b.fan_boost < a.fan_boost {
+            problems.push(format!(
+                "{name} boosts may not go down, {} at {}°C is followed by {} at {}°C",
+                a.fan_boost, a.temp, b.fan_boost, b.temp
+            ));
+        }
+    }
+    problems
+}
+
+/// Curve for freshly probed fans: quiet until 45°C, full boost from 62°C
+const STARTER_GRAPH: [(u8, u8); 4] = [(0, 0), (45, 0), (55, 100), (62, 255)];
+
+/// A commented json5 config for the probed hardware, the first two fans
+/// become the CPU and GPU with the sensors the firmware pairs them with
+pub fn starter(probes: &Probes) -> Result<String, String> {
+    let [cpu, gpu, ..] = probes.fans[..] else {
+        return Err(format!(
+            "Found {} fans, awc needs a CPU and a GPU fan",
+            probes.fans.len()
+        ));
+    };
+    // a fan paired with a sensor that didn't answer gets the first one that did
+    let device = |(fan_id, sen_id): (u8, u8)| -> Result<DeviceInfo, String> {
+        let sensor = if probes.sensors.contains(&sen_id) {
+            sen_id
+        } else {
+            *probes
+                .sensors
+                .first()
+                .ok_or_else(|| format!("Found no sensor for fan #{fan_id}"))?
+        };
+        Ok(DeviceInfo {
+            graph_type: GraphType::Linear,
+            graph: STARTER_GRAPH
+                .iter()
+                .map(|&(temp, fan_boost)| CoOrdinates { temp, fan_boost })
+                .collect(),
+            sensor,
+            fan: fan_id,
+        })
+    };
+    let config = AwcConfig {
+        disable_power_mode_on_startup: false,
+        cpu: device(cpu)?,
+        gpu: device(gpu)?,
+        interval: 5,
+    };
+
+    let mut s = String::from("// Written by `awc config init`, check it with `awc config check`\n");
+    s += &format!("// System id: {}\n", probes.sys_id);
+    let fans = probes.fans.iter().map(|(fan_id, _)| fan_id.to_string());
+    s += &format!("// Fans: {}\n", join_ids(fans));
+    let sensors = probes.sensors.iter().map(u8::to_string);
+    s += &format!("// Sensors: {}\n", join_ids(sensors));
+    let powers = probes.powers.iter().map(|mode| format!("{mode:#x}"));
+    s += &format!("// Power modes: {}\n", join_ids(powers));
+    s += "// The firmware doesn't say which fan cools what, the first one is taken as the CPU's\n";
+    s += &config.to_json5();
+    Ok(s)
+}
+
+fn join_ids(ids: impl Iterator<Item = String>) -> String {
+    let ids: Vec<_> = ids.collect();
+    if ids.is_empty() {
+        return String::from("none");
+    }
+    ids.join(", ")
+}
+
+/// The shape the config had before `AwcConfig`, graphs as `--path` lines
+#[derive(Deserialize)]
+struct LegacyConfig {
+    cpu_graph: String,
+    gpu_graph: String,
+    #[serde(default)]
+    disable_power_mode_on_startup: bool,
+    graph_type: Option<GraphType>,
+}
+
+/// Turns a two line graph file, or an old `cpu_graph`/`gpu_graph` config,
+/// into an [`AwcConfig`]. `interval` and `graph_type` are what `watch` was
+/// started with, `devices` the `(sensor, fan)` ids it used.
+pub fn migrate(
+    text: &str,
+    interval: Option<u64>,
+    graph_type: Option<GraphType>,
+    devices: ((u8, u8), (u8, u8)),
+) -> Result<AwcConfig, String> {
+    let (cpu_graph, gpu_graph, disable_power_mode_on_startup, graph_type) =
+        match json5::from_str::<LegacyConfig>(text) {
+            Ok(legacy) => (
+                line_to_coords(&legacy.cpu_graph).map_err(|e| format!("cpu_graph: {e}"))?,
+                line_to_coords(&legacy.gpu_graph).map_err(|e| format!("gpu_graph: {e}"))?,
+                legacy.disable_power_mode_on_startup,
+                graph_type.or(legacy.graph_type),
+            ),
+            Err(_) => {
+                let (cpu_graph, gpu_graph) = get_coords_from_string(text)?;
+                (cpu_graph, gpu_graph, false, graph_type)
+            }
+        };
+    let graph_type = graph_type.unwrap_or(GraphType::Linear);
+    let ((cpu_sensor, cpu_fan), (gpu_sensor, gpu_fan)) = devices;
+    Ok(AwcConfig {
+        disable_power_mode_on_startup,
+        cpu: DeviceInfo {
+            graph_type,
+            graph: cpu_graph,
+            sensor: cpu_sensor,
+            fan: cpu_fan,
+        },
+        gpu: DeviceInfo {
+            graph_type,
+            graph: gpu_graph,
+            sensor: gpu_sensor,
+            fan: gpu_fan,
+        },
+        interval: interval.unwrap_or(30),
+    })
+}
+
+/// Line and column of the dotted `key` in json5 `text`, found by name
+fn locate(text: &str, key: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    for name in key.split('.') {
+        offset += find_key(&text[offset..], name)?;
+    }
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    Some((line, column))
+}
+
+/// Offset of `name` used as a key, quoted or not
+fn find_key(text: &str, name: &str) -> Option<usize> {
+    text.match_indices(name).map(|(i, _)| i).find(|&i| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + name.len()..].trim_start_matches(['"', '\'']);
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_')
+            && after.trim_start().starts_with(':')
+    })
+}
+
+/// Objects are merged key by key, anything else (graphs included) is replaced
+fn merge_value(
+    base: &mut Value,
+    layer: Value,
+    key: &str,
+    source: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (name, value) in layer {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{key}.{name}")
+                };
+                let slot = base.entry(name).or_insert(Value::Null);
+                if !value.is_object() || !slot.is_object() {
+                    // a whole subtree is replaced, forget where its old keys came from
+                    let prefix = format!("{key}.");
+                    sources.retain(|k, _| !k.starts_with(&prefix));
+                }
+                if !slot.is_object() && value.is_object() {
+                    *slot = Value::Object(Default::default());
+                }
+                merge_value(slot, value, &key, source, sources);
             }
         }
+        (base, layer) => {
+            *base = layer;
+            sources.insert(key.to_string(), source.clone());
+        }
+    }
+}
+
+/// json5 of the merged config, every value commented with its source
+impl fmt::Display for Effective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_object(
+            f: &mut fmt::Formatter<'_>,
+            effective: &Effective,
+            object: &serde_json::Map<String, Value>,
+            key: &str,
+            depth: usize,
+        ) -> fmt::Result {
+            let indent = "  ".repeat(depth + 1);
+            for (name, value) in object {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{key}.{name}")
+                };
+                match value {
+                    Value::Object(inner) => {
+                        writeln!(f, "{indent}{name}: {{")?;
+                        write_object(f, effective, inner, &key, depth + 1)?;
+                        writeln!(f, "{indent}}},")?;
+                    }
+                    value => {
+                        write!(f, "{indent}{name}: {value},")?;
+                        match effective.sources.get(&key) {
+                            Some(source) => writeln!(f, " // {source}")?,
+                            None => writeln!(f)?,
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        writeln!(f, "{{")?;
+        if let Value::Object(object) = &self.value {
+            write_object(f, self, object, "", 0)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::{
+        controller::{probe, BoostCurve},
+        simulated::with_simulated,
+    };
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("awc-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("awc.d")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn explicit_config_beats_user_and_system_and_drop_ins_follow_in_order() {
+        let dir = dir("lookup");
+        let system = dir.join("awc.conf");
+        let user = dir.join("user.conf");
+        let drop_ins = dir.join("awc.d");
+        fs::write(&system, "{}").unwrap();
+        for name in ["20-late.conf", "10-early.conf", "README"] {
+            fs::write(drop_ins.join(name), "{}").unwrap();
+        }
+        let expected_drop_ins = [
+            drop_ins.join("10-early.conf"),
+            drop_ins.join("20-late.conf"),
+        ];
+
+        let files = lookup_in(None, Some(user.clone()), &system, &drop_ins).unwrap();
+        assert_eq!(files[0], system);
+        assert_eq!(files[1..], expected_drop_ins);
+
+        fs::write(&user, "{}").unwrap();
+        let files = lookup_in(None, Some(user.clone()), &system, &drop_ins).unwrap();
+        assert_eq!(files[0], user);
+
+        let explicit = dir.join("explicit.conf");
+        assert!(lookup_in(Some(explicit.clone()), Some(user), &system, &drop_ins).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drop_ins_override_key_by_key_and_remember_their_source() {
+        let dir = dir("merge");
+        let base = dir.join("awc.conf");
+        let drop_in = dir.join("awc.d/10-quiet.conf");
+        fs::write(
+            &base,
+            r#"{
+                disable_power_mode_on_startup: false,
+                interval: 30,
+                cpu: { graph_type: "linear", graph: [{ temp: 0, fan_boost: 0 }], sensor: 1, fan: 50 },
+                gpu: { graph_type: "linear", graph: [{ temp: 0, fan_boost: 0 }], sensor: 6, fan: 51 },
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            &drop_in,
+            "{ interval: 5, cpu: { graph_type: 'step', graph: [{ temp: 50, fan_boost: 100 }] } }",
+        )
+        .unwrap();
+
+        let effective = Effective::merge(&[base.clone(), drop_in.clone()]).unwrap();
+        let config = effective.config().unwrap();
+        assert_eq!(config.interval, 5);
+        assert_eq!(config.cpu.graph_type, GraphType::Step);
+        assert_eq!(config.cpu.graph.len(), 1);
+        let cpu_curve = BoostCurve::new(config.cpu.graph.clone(), config.cpu.graph_type);
+        assert_eq!(cpu_curve.boost(20), 100);
+        assert_eq!(config.cpu.fan, 50);
+        let (base, drop_in) = (Source::File(base), Source::File(drop_in));
+        assert_eq!(effective.sources["interval"], drop_in);
+        assert_eq!(effective.sources["cpu.graph"], drop_in);
+        assert_eq!(effective.sources["cpu.sensor"], base);
+        assert_eq!(effective.sources["gpu.graph_type"], base);
+        assert!(effective
+            .to_string()
+            .contains(&format!("interval: 5, // {drop_in}")));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_points_at_the_offending_key() {
+        let dir = dir("check");
+        let typo = dir.join("typo.conf");
+        fs::write(&typo, "{\n  interval: 5,\n  cpu: { graph_typ: 'step' },\n}").unwrap();
+        let problems = check(std::slice::from_ref(&typo), &[], None);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path.as_ref(), Some(&typo));
+        assert_eq!(problems[0].location, Some((3, 10)));
+        assert!(problems[0].message.contains("graph_typ"));
+
+        let bad = dir.join("bad.conf");
+        fs::write(
+            &bad,
+            r#"{
+  disable_power_mode_on_startup: false,
+  interval: 5,
+  cpu: { graph_type: "linear", graph: [{ temp: 60, fan_boost: 0 }, { temp: 40, fan_boost: 9 }], sensor: 1, fan: 50 },
+  gpu: { graph_type: "linear", graph: [{ temp: 0, fan_boost: 0 }], sensor: 6, fan: 52 },
+}"#,
+        )
+        .unwrap();
+        let probes = Probes {
+            fans: vec![(50, 1), (51, 6)],
+            sensors: vec![1, 6],
+            ..Default::default()
+        };
+        let problems: Vec<_> = check(std::slice::from_ref(&bad), &[], Some(&probes))
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with(&format!("{}:4:32: cpu.graph", bad.display())));
+        assert!(problems[1].contains("gpu.fan 52 is not a fan"));
+
+        // and a daemon won't start on it either
+        let empty = dir.join("empty.conf");
+        fs::write(&empty, "{ gpu: { graph: [] }, interval: 0 }").unwrap();
+        let source = ConfigSource {
+            files: vec![bad.clone(), empty.clone()],
+            sets: vec![],
+        };
+        let error = source.load().unwrap_err();
+        let lines: Vec<_> = error.lines().collect();
+        assert_eq!(lines.len(), 3, "{error}");
+        assert!(lines[0].starts_with(&format!("{}: interval has to be", empty.display())));
+        assert!(lines[1].starts_with(&format!("{}: cpu.graph temperatures", bad.display())));
+        assert_eq!(
+            lines[2],
+            format!("{}: gpu.graph has no points", empty.display())
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn starter_config_checks_clean() {
+        with_simulated(|_| {
+            let dir = dir("init");
+            let path = dir.join("awc.conf");
+            let probes = probe().unwrap();
+            fs::write(&path, starter(&probes).unwrap()).unwrap();
+
+            assert!(check(std::slice::from_ref(&path), &[], Some(&probes)).is_empty());
+            let config = Effective::load(&[path], &[]).unwrap().config().unwrap();
+            assert_eq!(config.devices(), ((1, 50), (6, 51)));
+
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn migrates_graph_files_and_old_configs() {
+        let devices = ((1, 50), (6, 51));
+        let config = migrate(
+            "(0 0), (50 100)\n(0 0), (70 200)",
+            Some(5),
+            Some(GraphType::Step),
+            devices,
+        )
+        .unwrap();
+        assert_eq!(config.interval, 5);
+        assert_eq!(config.cpu.graph_type, GraphType::Step);
+        assert_eq!(config.gpu.graph[1].fan_boost, 200);
+        assert_eq!(config.devices(), devices);
+
+        let old = r#"{ cpu_graph: "(0 0), (50 100)", gpu_graph: "(0 0), (70 200)",
+            disable_power_mode_on_startup: true, graph_type: "step" }"#;
+        let config = migrate(old, None, None, devices).unwrap();
+        assert_eq!(config.interval, 30);
+        assert!(config.disable_power_mode_on_startup);
+        assert_eq!(config.gpu.graph_type, GraphType::Step);
+        let config = migrate(old, None, Some(GraphType::Linear), devices).unwrap();
+        assert_eq!(config.cpu.graph_type, GraphType::Linear);
+
+        // what gets written reads back the same
+        let reread: AwcConfig = json5::from_str(&config.to_json5()).unwrap();
+        assert_eq!(reread.to_json5(), config.to_json5());
+    }
+
+    #[test]
+    fn set_beats_env_which_beats_files() {
+        let dir = dir("overrides");
+        let path = dir.join("awc.conf");
+        let full = migrate(
+            "(0 0), (50 100)\n(0 0), (70 200)",
+            Some(30),
+            None,
+            ((1, 50), (6, 51)),
+        );
+        fs::write(&path, full.unwrap().to_json5()).unwrap();
+        let file = Source::File(path.clone());
+
+        // every key of a full config can be overridden
+        let effective = Effective::merge(std::slice::from_ref(&path)).unwrap();
+        let keys: Vec<_> = effective.sources.keys().map(String::as_str).collect();
+        let mut all = KEYS.to_vec();
+        all.sort();
+        assert_eq!(keys, all);
+
+        // the only key no other test looks at, tests share the env
+        env::set_var("AWC_DISABLE_POWER_MODE_ON_STARTUP", "true");
+        let sets = [
+            "cpu.graph_type=step".parse().unwrap(),
+            "gpu.graph=[{ temp: 0, fan_boost: 9 }]".parse().unwrap(),
+            "interval=5".parse().unwrap(),
+            "interval=7".parse().unwrap(),
+        ];
+        let effective = Effective::load(std::slice::from_ref(&path), &sets).unwrap();
+        env::remove_var("AWC_DISABLE_POWER_MODE_ON_STARTUP");
+        let config = effective.config().unwrap();
+        assert!(config.disable_power_mode_on_startup);
+        assert_eq!(config.cpu.graph_type, GraphType::Step);
+        assert_eq!(config.gpu.graph[0].fan_boost, 9);
+        assert_eq!(config.interval, 7);
+        assert_eq!(
+            effective.sources["disable_power_mode_on_startup"],
+            Source::Env(String::from("AWC_DISABLE_POWER_MODE_ON_STARTUP"))
+        );
+        assert_eq!(effective.sources["interval"], Source::Set);
+        assert_eq!(effective.sources["cpu.fan"], file);
+        assert!(effective.to_string().contains("interval: 7, // --set"));
+
+        assert!("cpu.interval=10".parse::<Override>().is_err());
+        assert!("interval".parse::<Override>().is_err());
 
-        panic!("Can't find Config file")
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,223 @@
+use std::{
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Everything the outside world can ask a running [`Controller`] to do
+///
+/// [`Controller`]: crate::controller::Controller
+#[derive(Debug, Clone)]
+pub enum ControlCommand {
+    /// Hand the fans back to the firmware and stop watching
+    Quit,
+    TogglePowerMode,
+    SetPowerMode(bool),
+    ShowInfo,
+    /// Reads back every sensor and fan
+    Status,
+    /// Re-read the curve files, keeping the current curves if they are broken
+    Reload,
+    /// Skip the rest of the interval and run the next tick now
+    Next,
+    SetProfile(String),
+    /// Pin a fan to `boost`, ignoring its curve until cleared or, with a
+    /// `duration`, until it runs out
+    SetBoost {
+        fan_id: u8,
+        boost: u8,
+        duration: Option<Duration>,
+    },
+    /// Hand a pinned fan, or every fan with `None`, back to its curve
+    ClearBoost {
+        fan_id: Option<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanStatus {
+    pub name: String,
+    pub fan_id: u8,
+    pub sensor_id: u8,
+    pub temp: i64,
+    pub rpm: i64,
+    pub boost: u8,
+    pub health: String,
+    /// Boost the fan is pinned to by hand, if any
+    pub pinned: Option<u8>,
+    /// Policy limit the boost is held to, if any
+    pub clamped: Option<String>,
+    /// How many times the watchdog found the fan stuck
+    #[serde(default)]
+    pub watchdog_fired: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub power_mode: u8,
+    pub profile: String,
+    pub emergency: bool,
+    pub fans: Vec<FanStatus>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Power Mode: {}", self.power_mode)?;
+        writeln!(f, "Profile: {}", self.profile)?;
+        if self.emergency {
+            writeln!(f, "Thermal emergency")?;
+        }
+        for fan in &self.fans {
+            writeln!(f, "{}:", fan.name)?;
+            writeln!(f, " Sensor #{} Temp: {}", fan.sensor_id, fan.temp)?;
+            write!(
+                f,
+                " Fan #{} boost: {}/255 rpm: {} health: {}",
+                fan.fan_id, fan.boost, fan.rpm, fan.health
+            )?;
+            if let Some(pinned) = fan.pinned {
+                write!(f, " pinned: {pinned}")?;
+            }
+            if let Some(limit) = &fan.clamped {
+                write!(f, " held by the {limit}")?;
+            }
+            writeln!(f)?;
+            writeln!(f, " Watchdog fired: {} times", fan.watchdog_fired)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Message(String),
+    Status(Status),
+}
+
+impl From<String> for Reply {
+    fn from(message: String) -> Self {
+        Reply::Message(message)
+    }
+}
+
+impl From<&str> for Reply {
+    fn from(message: &str) -> Self {
+        Reply::Message(message.to_string())
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Message(message) => write!(f, "{message}"),
+            Reply::Status(status) => write!(f, "{status}"),
+        }
+    }
+}
+
+/// What the controller answers to a command
+pub type Ack = Result<Reply, String>;
+
+#[derive(Debug)]
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub ack: Sender<Ack>,
+}
+
+impl ControlRequest {
+    pub fn reply(self, ack: Ack) {
+        // the sender may have stopped waiting, nothing to do about it
+        let _ = self.ack.send(ack);
+    }
+}
+
+/// The sending half of the command channel, cheap to clone
+#[derive(Debug, Clone)]
+pub struct ControlHandle {
+    tx: Sender<ControlRequest>,
+}
+
+impl ControlHandle {
+    /// Queues `command` and waits for the controller to acknowledge it
+    pub fn send(&self, command: ControlCommand) -> Ack {
+        let (ack, ack_rx) = mpsc::channel();
+        self.tx
+            .send(ControlRequest { command, ack })
+            .map_err(|_| String::from("Controller is not running"))?;
+        ack_rx
+            .recv()
+            .map_err(|_| String::from("Controller stopped before answering"))?
+    }
+}
+
+/// Follows whichever controller is currently running, so the socket and
+/// stdin keep working across pause and resume
+#[derive(Debug, Clone, Default)]
+pub struct SharedHandle {
+    current: Arc<Mutex<Option<ControlHandle>>>,
+}
+
+impl SharedHandle {
+    pub fn set(&self, handle: Option<ControlHandle>) {
+        *self.current.lock().unwrap() = handle;
+    }
+
+    pub fn send(&self, command: ControlCommand) -> Ack {
+        let handle = self.current.lock().unwrap().clone();
+        match handle {
+            Some(handle) => handle.send(command),
+            None => Err(String::from("Watch is paused")),
+        }
+    }
+}
+
+pub fn channel() -> (ControlHandle, Receiver<ControlRequest>) {
+    let (tx, rx) = mpsc::channel();
+    (ControlHandle { tx }, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::simulated::{self, drive, test_controller, with_simulated};
+
+    #[test]
+    fn back_to_back_commands_are_all_acknowledged() {
+        with_simulated(|sim| {
+            let (acks, pinned, unknown) = drive(&mut test_controller(), |handle| {
+                let (first, second) = (handle.clone(), handle.clone());
+                let a = thread::spawn(move || {
+                    first.send(ControlCommand::SetBoost {
+                        fan_id: 50,
+                        boost: 120,
+                        duration: None,
+                    })
+                });
+                let b = thread::spawn(move || {
+                    second.send(ControlCommand::SetBoost {
+                        fan_id: 51,
+                        boost: 130,
+                        duration: None,
+                    })
+                });
+                let acks = [a.join().unwrap(), b.join().unwrap()];
+                let pinned = (sim.boost(50), sim.boost(51));
+                let unknown = handle.send(ControlCommand::SetProfile(String::from("silent")));
+                (acks, pinned, unknown)
+            });
+
+            assert!(acks.iter().all(|ack| ack.is_ok()));
+            assert_eq!(pinned, (120, 130));
+            assert!(unknown.is_err());
+            assert_eq!(sim.boost(50), 0);
+        });
+    }
+}
@@ -1,31 +1,53 @@
 use std::{
+    collections::BTreeMap,
     fs::OpenOptions,
     io::{Read, Write},
-    sync::atomic::{AtomicBool, AtomicIsize, Ordering},
-    thread,
-    time::{Duration, SystemTime},
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
 };
 
-use crate::GraphType;
+use serde::Deserialize;
 
-const ACPI_CALL_FPATH: &'static str = "/proc/acpi/call";
+use crate::{
+    control::{Ack, ControlCommand, ControlRequest, FanStatus, Reply, Status},
+    emergency::ThermalEmergency,
+    events::{emit, Event},
+    fan_health::{FanHealth, FanHealthMonitor},
+    feed::{self, Sample},
+    instance::acpi_call_holders,
+    log::{bold, green, yellow, Level},
+    policy::{Limit, Policy},
+    recorder::Recorder,
+    reload::CurveSource,
+    rpm_control::{rpm_for_boost, CalibrationPoint, RpmTarget},
+    sd_notify::Notifier,
+    shutdown::restore_firmware_control,
+    simulated,
+    state::{self, Pin, State},
+    watchdog::{fired_count, LastFanRPMRecorded, Watchdog},
+    GraphType,
+};
 
-const RESET: &'static str = "\x1b[0m";
-const BOLD: &'static str = "\x1b[1m";
-const RED: &'static str = "\x1b[31m";
-const GREEN: &'static str = "\x1b[32m";
-const YELLOW: &'static str = "\x1b[33m";
-const BLUE: &'static str = "\x1b[34m";
-const CYAN: &'static str = "\x1b[36m";
+const ACPI_CALL_FPATH: &str = "/proc/acpi/call";
 
-#[derive(Debug)]
-struct AlienDevInfo {
+pub const DEFAULT_PROFILE: &str = "default";
+pub const POWER_MODE_DENIED: &str = "Power mode is turned off by the administrator's policy";
+pub const POWER_MODE_EMERGENCY: &str = "Power mode stays off during a thermal emergency";
+
+#[derive(Debug, Clone, Copy)]
+pub struct AlienDevInfo {
     fan_id: u8,
     sen_id: u8,
     name: &'static str,
 }
 
-const ALIEN_DEVICES: [AlienDevInfo; 2] = [
+/// The CPU and GPU, in that order
+pub type Devices = [AlienDevInfo; 2];
+
+/// The ids of the machine awc was written for, used without a config
+pub const ALIEN_DEVICES: Devices = [
     AlienDevInfo {
         fan_id: 50,
         sen_id: 1,
@@ -38,426 +60,1004 @@ const ALIEN_DEVICES: [AlienDevInfo; 2] = [
     },
 ];
 
+/// The devices with the `(sensor, fan)` ids a config names
+pub fn devices_from_ids(cpu: (u8, u8), gpu: (u8, u8)) -> Devices {
+    [
+        AlienDevInfo {
+            fan_id: cpu.1,
+            sen_id: cpu.0,
+            name: "CPU",
+        },
+        AlienDevInfo {
+            fan_id: gpu.1,
+            sen_id: gpu.0,
+            name: "GPU",
+        },
+    ]
+}
+
+/// `(sensor, fan)` ids of the CPU and GPU
+pub fn device_ids(devices: &Devices) -> ((u8, u8), (u8, u8)) {
+    let [cpu, gpu] = devices;
+    ((cpu.sen_id, cpu.fan_id), (gpu.sen_id, gpu.fan_id))
+}
+
+#[derive(Debug, Clone)]
+pub enum FanCurve {
+    Boost(BoostCurve),
+    /// Temperature to rpm, with the feedback loop that gets the fan there
+    Rpm(RpmTarget),
+}
+
+impl FanCurve {
+    /// The rpm the fan's calibration table predicts for `boost`, if it has one
+    pub fn expected_rpm(&self, boost: u8) -> Option<u32> {
+        match self {
+            FanCurve::Boost(curve) => curve
+                .calibration
+                .as_deref()
+                .map(|calibration| rpm_for_boost(boost, calibration)),
+            FanCurve::Rpm(target) => target.expected_rpm(boost),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AlienDevGraphInfo {
-    dev: &'static AlienDevInfo,
-    graph: Vec<CoOrdinates>,
+    dev: AlienDevInfo,
+    curve: FanCurve,
+    health: FanHealthMonitor,
     last_fan_boost: u8,
     last_fan_rpm_recorded: LastFanRPMRecorded,
+    /// The policy limit the last tick held the boost to, if any
+    clamped: Option<Limit>,
+    /// Whether the last tick found another tool's boost on the fan
+    conflicting: bool,
+    /// The boost the last run left the fan at, for the first tick after a restart
+    resume_boost: Option<u8>,
 }
 
-#[derive(Debug)]
-pub struct LastFanRPMRecorded {
-    rpm: i64,
-    ts: SystemTime,
+#[derive(Debug, Clone, Copy)]
+struct ManualBoost {
+    boost: u8,
+    /// When a temporary pin runs out
+    until: Option<Instant>,
+}
+
+/// What the watch loop does after handling a command
+enum AfterCommand {
+    Wait,
+    Tick,
+    Quit,
 }
 
 pub struct Controller {
     alien_dev_graph_infos: [AlienDevGraphInfo; 2],
+    profiles: BTreeMap<String, [FanCurve; 2]>,
+    active_profile: String,
+    /// Fans pinned to a boost by hand, by fan id
+    manual_boosts: BTreeMap<u8, ManualBoost>,
     power_mode: u8,
+    original_power_mode: u8,
+    emergency: ThermalEmergency,
+    /// Set when running under systemd with `Type=notify`
+    notifier: Option<Notifier>,
+    /// Where `Reload` reads the curves from
+    curve_source: Option<CurveSource>,
+    state_file: Option<PathBuf>,
+    /// Whether the last tick wrote the boosts, as opposed to leaving them to
+    /// the firmware, so their readbacks should match `last_fan_boost`
+    owns_boosts: bool,
+    /// What was last written to `state_file`
+    saved_state: Option<State>,
+    policy: Option<Policy>,
+    recorder: Option<Recorder>,
 }
 
 impl Controller {
-    pub fn new(alien_dev_graph_infos: [AlienDevGraphInfo; 2]) -> Self {
-        // let alien_dev_graph_infos = load_graph_from_string(graphs_string);
+    /// Drives `devices` with `curves`, which become the `default` profile
+    pub fn new(devices: Devices, curves: [FanCurve; 2], emergency: ThermalEmergency) -> Self {
+        let alien_dev_graph_infos = get_alien_dev_infos(devices, curves);
         let power_mode = get_power_mode() as u8;
-        dbg!(&alien_dev_graph_infos);
+        for info in &alien_dev_graph_infos {
+            debug!(
+                "{} fan #{} curve: {:?}",
+                info.dev.name, info.dev.fan_id, info.curve
+            );
+        }
+        let default_curves = [
+            alien_dev_graph_infos[0].curve.clone(),
+            alien_dev_graph_infos[1].curve.clone(),
+        ];
 
         Self {
             power_mode,
+            original_power_mode: power_mode,
+            profiles: BTreeMap::from([(String::from(DEFAULT_PROFILE), default_curves)]),
+            active_profile: String::from(DEFAULT_PROFILE),
+            manual_boosts: BTreeMap::new(),
             alien_dev_graph_infos,
+            emergency,
+            notifier: None,
+            curve_source: None,
+            state_file: None,
+            owns_boosts: false,
+            saved_state: None,
+            policy: None,
+            recorder: None,
         }
     }
 
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    /// The power mode to hand back on exit, when awc changed it before the
+    /// controller started
+    pub fn set_original_power_mode(&mut self, mode: u8) {
+        self.original_power_mode = mode;
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = Some(policy);
+    }
+
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn set_curve_source(&mut self, source: CurveSource) {
+        self.curve_source = Some(source);
+    }
+
+    /// Picks up the profile, manual overrides, last boosts and, after a
+    /// crash, the original power mode from `path`, and keeps it up to date
+    /// from now on
+    pub fn restore_state(&mut self, path: PathBuf) {
+        if let Some(saved) = state::load(&path) {
+            if self.profiles.contains_key(&saved.profile) {
+                self.active_profile = saved.profile.clone();
+                self.apply_active_profile();
+            }
+            for (fan_id, pin) in &saved.pinned {
+                let until = match pin.until {
+                    Some(unix) => match state::unix_to_instant(unix) {
+                        Some(until) => Some(until),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                if self.has_fan(*fan_id) {
+                    self.manual_boosts.insert(
+                        *fan_id,
+                        ManualBoost {
+                            boost: pin.boost,
+                            until,
+                        },
+                    );
+                }
+            }
+            for info in &mut self.alien_dev_graph_infos {
+                info.resume_boost = saved.boosts.get(&info.dev.fan_id).copied();
+            }
+            if let Some(mode) = saved.original_power_mode {
+                self.original_power_mode = mode;
+            }
+            info!(
+                "Resumed profile {} with {} pinned fans from {}",
+                self.active_profile,
+                self.manual_boosts.len(),
+                path.display()
+            );
+        }
+        self.state_file = Some(path);
+    }
+
+    pub fn add_profile(&mut self, name: String, curves: [FanCurve; 2]) {
+        self.profiles.insert(name, curves);
+    }
+
+    /// Runs a tick every `update_interval_in_seconds` and handles commands as
+    /// soon as they arrive in between. Returns after `Quit`, or once every
+    /// sender of `commands` is gone.
     pub fn watch(
         &mut self,
         update_interval_in_seconds: u64,
-        graph_type: GraphType,
-        exit_sig: &AtomicIsize,
+        watchdog: &Watchdog,
+        commands: &Receiver<ControlRequest>,
     ) {
-        let milli_sec_dur = Duration::from_millis(200);
+        // commands that changed the boosts are acknowledged once they're applied
+        let mut pending_acks = Vec::<(ControlRequest, Ack)>::new();
+        let ping_every = self.notifier.as_ref().and_then(Notifier::watchdog_interval);
+        let mut ready = false;
         loop {
-            if self.power_mode == 0 {
-                let current_time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
-                print!("{CYAN}{}{RESET}\n", current_time);
-                print!("Power Mode: {BOLD}{}{RESET}\n", self.power_mode);
-                for info in &mut self.alien_dev_graph_infos {
-                    {
-                        // Some bug fix where fans stuck at the same rpm and won't change
-                        let rpm = get_fan_rpm(info.dev.fan_id);
-                        if !(rpm == 0 && info.last_fan_boost == 0)
-                            && info.last_fan_rpm_recorded.rpm == rpm
-                            && info.last_fan_rpm_recorded.ts.elapsed().unwrap().as_secs()
-                                > update_interval_in_seconds * 3
-                        {
-                            let result = set_fan_boost(info.dev.fan_id, 0);
-                            print!("Fan {BOLD}#{}{RESET} Boost: {YELLOW}0{RESET}/255 RPM: {CYAN}{}{RESET} Result: {}\n",info.dev.fan_id, rpm, result);
-                            thread::sleep(milli_sec_dur);
+            self.tick(watchdog);
+            for (request, ack) in pending_acks.drain(..) {
+                request.reply(ack);
+            }
+            self.save_state(false);
+            // read once, so the recorded row, the sample and systemd agree
+            let wants_samples = feed::wants_samples();
+            let status = (self.recorder.is_some() || wants_samples || self.notifier.is_some())
+                .then(|| self.status());
+            if let (Some(recorder), Some(status)) = (&mut self.recorder, &status) {
+                recorder.record(status);
+            }
+            if let (true, Some(status)) = (wants_samples, &status) {
+                feed::publish_sample(Sample::new(status.clone()));
+            }
+            if let (Some(notifier), Some(status)) = (&self.notifier, &status) {
+                if !ready {
+                    notifier.ready();
+                    ready = true;
+                }
+                notifier.status(&status_line(status));
+                notifier.watchdog();
+            }
+
+            let deadline = Instant::now() + Duration::from_secs(update_interval_in_seconds);
+            let mut next_ping = ping_every.map(|every| Instant::now() + every);
+            loop {
+                // wake up for the systemd watchdog even if the interval is longer
+                if let (Some(ping), Some(every)) = (next_ping, ping_every) {
+                    if Instant::now() >= ping {
+                        if let Some(notifier) = &self.notifier {
+                            notifier.watchdog();
                         }
+                        next_ping = Some(ping + every);
                     }
-                    let rpm = get_fan_rpm(info.dev.fan_id);
-                    if rpm != info.last_fan_rpm_recorded.rpm {
-                        info.last_fan_rpm_recorded = LastFanRPMRecorded {
-                            rpm,
-                            ts: SystemTime::now(),
-                        };
+                }
+                let wake = next_ping.map_or(deadline, |ping| ping.min(deadline));
+                if Instant::now() >= deadline {
+                    break;
+                }
+                let timeout = wake.saturating_duration_since(Instant::now());
+                match commands.recv_timeout(timeout) {
+                    Ok(request) => match self.handle_command(&request.command) {
+                        (ack, AfterCommand::Wait) => request.reply(ack),
+                        (ack, AfterCommand::Tick) => {
+                            pending_acks.push((request, ack));
+                            break;
+                        }
+                        (ack, AfterCommand::Quit) => {
+                            request.reply(ack);
+                            self.save_state(true);
+                            self.notify_stopping();
+                            return;
+                        }
+                    },
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        restore_firmware_control(&self.devices(), self.original_power_mode);
+                        self.save_state(true);
+                        self.notify_stopping();
+                        return;
                     }
-                    let temp = get_temp(info.dev.sen_id) as u8;
-                    print!(
-                        "{} Sensor {BOLD}#{}{RESET} Temp: {YELLOW}{}{RESET}\n",
-                        info.dev.name, info.dev.sen_id, temp
-                    );
-                    let boost = match graph_type {
-                        GraphType::Linear => get_boost_from_temp_linear(temp, &info.graph),
-                        GraphType::Step => get_boost_from_temp_step(temp, &info.graph),
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, watchdog: &Watchdog) {
+        let emergency = self.check_thermal_emergency();
+        let protective = self.check_fan_health();
+        // also catches power mode that was on before the daemon started
+        if emergency || protective || !self.allows_power_mode() {
+            self.set_mode(false);
+        }
+
+        if emergency {
+            warn!("Thermal emergency, all fans at 255");
+            return;
+        }
+        if self.power_mode != 0 {
+            self.owns_boosts = false;
+            return;
+        }
+        if self.owns_boosts {
+            self.check_conflicts();
+        }
+        self.owns_boosts = true;
+
+        let now = Instant::now();
+        self.manual_boosts
+            .retain(|_, manual| manual.until.is_none_or(|until| until > now));
+
+        let clock = chrono::Local::now().time();
+        for (device, info) in self.alien_dev_graph_infos.iter_mut().enumerate() {
+            let rpm = get_fan_rpm(info.dev.fan_id);
+            if watchdog.check(
+                info.dev.fan_id,
+                &mut info.last_fan_rpm_recorded,
+                rpm,
+                info.last_fan_boost,
+            ) {
+                emit(Event::WatchdogFired {
+                    fan_id: info.dev.fan_id,
+                });
+            }
+            let temp = get_temp(info.dev.sen_id) as u8;
+            let fan_id = info.dev.fan_id;
+            let manual_boost = self.manual_boosts.get(&fan_id);
+            let resume_boost = info.resume_boost.take();
+            let wanted = match &mut info.curve {
+                _ if protective => 255,
+                _ if manual_boost.is_some() => {
+                    debug!(fan_id = fan_id; "Fan #{fan_id} is pinned by hand");
+                    manual_boost.unwrap().boost
+                }
+                FanCurve::Rpm(target) => {
+                    // after a restart the rpm still is the firmware's, carry
+                    // on from the boost the last run got to instead
+                    let boost = match resume_boost {
+                        Some(boost) => target.resume(temp, boost),
+                        None => target.next_boost(temp, rpm, info.last_fan_boost),
                     };
-                    if boost != info.last_fan_boost {
-                        let result = set_fan_boost(info.dev.fan_id, boost);
-                        print!("Fan {BOLD}#{}{RESET} Boost: {YELLOW}{}{RESET}/255 RPM: {GREEN}{}{RESET} Result: {}\n",info.dev.fan_id, boost,rpm, result);
-                        info.last_fan_boost = boost;
-                    } else {
-                        let rpm = get_fan_rpm(info.dev.fan_id);
-                        print!("Fan {BOLD}#{}{RESET} Boost: {YELLOW}{}{RESET}/255 RPM: {GREEN}{}{RESET}\n",info.dev.fan_id, info.last_fan_boost, rpm);
-                    }
+                    let target_rpm = target.last_target().unwrap_or_default();
+                    debug!(fan_id = fan_id, target_rpm = target_rpm; "Fan #{fan_id} target rpm {target_rpm}");
+                    boost
+                }
+                FanCurve::Boost(curve) => curve.boost(temp),
+            };
+            // a failed fan's neighbours stay at full boost, policy or not
+            let (boost, clamped) = match &self.policy {
+                Some(policy) if !protective => policy.clamp(device, temp, wanted, clock),
+                _ => (wanted, None),
+            };
+            if let Some(limit) = clamped {
+                debug!(fan_id = fan_id; "Fan #{fan_id} held to {boost} by the {limit}, wanted {wanted}");
+                if info.clamped != clamped {
+                    emit(Event::BoostClamped {
+                        fan_id: info.dev.fan_id,
+                        wanted,
+                        boost,
+                        limit,
+                    });
                 }
             }
-            for i in 0..(update_interval_in_seconds * 5) {
-                let sig_val = exit_sig.load(Ordering::SeqCst);
-                if sig_val != 0 {
-                    exit_sig.store(0, Ordering::SeqCst);
-                    match sig_val {
-                        -1 => {
-                            if self.power_mode == 0 {
-                                set_both_fan_boosts(0);
-                            } else {
-                                self.toggle_mode();
-                            }
-                            return;
-                        }
-                        1 => {
-                            self.toggle_mode();
-                        }
-                        2 => show_all_info(),
-                        3 => {
-                            if self.power_mode == 0 {
-                                set_both_fan_boosts(0);
-                            }
-                        }
-                        4 => {
-                            break;
+            info.clamped = clamped;
+            // a boost change is news, the same boost again only with --verbose
+            let level = if boost != info.last_fan_boost {
+                let result = set_fan_boost(fan_id, boost);
+                info.last_fan_boost = boost;
+                if result != 0 {
+                    warn!(fan_id = fan_id, result = result; "Fan #{fan_id} boost {boost} failed: {result}");
+                }
+                Level::Info
+            } else {
+                Level::Debug
+            };
+            log!(
+                level,
+                sensor_id = info.dev.sen_id,
+                temp = temp,
+                fan_id = fan_id,
+                boost = boost,
+                rpm = rpm;
+                "{} {temp}°C fan #{fan_id} boost {boost}/255 rpm {rpm}",
+                info.dev.name
+            );
+        }
+    }
+
+    fn handle_command(&mut self, command: &ControlCommand) -> (Ack, AfterCommand) {
+        match command {
+            ControlCommand::Quit => {
+                restore_firmware_control(&self.devices(), self.original_power_mode);
+                (
+                    Ok("Fans handed back to the firmware".into()),
+                    AfterCommand::Quit,
+                )
+            }
+            ControlCommand::TogglePowerMode
+                if self.power_mode == 0 && self.emergency.is_active() =>
+            {
+                (Err(POWER_MODE_EMERGENCY.into()), AfterCommand::Wait)
+            }
+            ControlCommand::SetPowerMode(true) if self.emergency.is_active() => {
+                (Err(POWER_MODE_EMERGENCY.into()), AfterCommand::Wait)
+            }
+            ControlCommand::TogglePowerMode
+                if self.power_mode == 0 && !self.allows_power_mode() =>
+            {
+                (Err(POWER_MODE_DENIED.into()), AfterCommand::Wait)
+            }
+            ControlCommand::SetPowerMode(true) if !self.allows_power_mode() => {
+                (Err(POWER_MODE_DENIED.into()), AfterCommand::Wait)
+            }
+            ControlCommand::TogglePowerMode => {
+                self.toggle_mode();
+                (
+                    Ok(format!("Power mode is {}", self.power_mode).into()),
+                    AfterCommand::Wait,
+                )
+            }
+            ControlCommand::SetPowerMode(enabled) => {
+                self.set_mode(*enabled);
+                (
+                    Ok(format!("Power mode is {}", self.power_mode).into()),
+                    AfterCommand::Tick,
+                )
+            }
+            ControlCommand::Status => (Ok(Reply::Status(self.status())), AfterCommand::Wait),
+            ControlCommand::ShowInfo => {
+                // the watchdog counts only mean something in this process,
+                // one line each so journald keeps them apart
+                for line in self.status().to_string().lines() {
+                    info!("{line}");
+                }
+                (Ok("Info shown".into()), AfterCommand::Wait)
+            }
+            ControlCommand::Reload => {
+                let Some(source) = &self.curve_source else {
+                    return (
+                        Err(String::from("No curve files to reload")),
+                        AfterCommand::Wait,
+                    );
+                };
+                match source.load() {
+                    Ok(profiles) => {
+                        // every profile is swapped at once, between two ticks
+                        self.profiles = profiles;
+                        if !self.profiles.contains_key(&self.active_profile) {
+                            self.active_profile = String::from(DEFAULT_PROFILE);
                         }
-                        _ => {}
+                        self.apply_active_profile();
+                        (
+                            Ok(
+                                format!("Reloaded the curves, profile {}", self.active_profile)
+                                    .into(),
+                            ),
+                            AfterCommand::Tick,
+                        )
                     }
+                    Err(e) => (
+                        Err(format!("Kept the current curves: {e}")),
+                        AfterCommand::Wait,
+                    ),
+                }
+            }
+            ControlCommand::Next => (Ok("Updating now".into()), AfterCommand::Tick),
+            ControlCommand::SetProfile(name) => {
+                if !self.profiles.contains_key(name) {
+                    return (Err(format!("Unknown profile {name}")), AfterCommand::Wait);
+                }
+                self.active_profile = name.clone();
+                self.apply_active_profile();
+                emit(Event::ProfileChanged {
+                    profile: name.clone(),
+                });
+                (
+                    Ok(format!("Switched to profile {name}").into()),
+                    AfterCommand::Tick,
+                )
+            }
+            ControlCommand::SetBoost {
+                fan_id,
+                boost,
+                duration,
+            } => {
+                if self.has_fan(*fan_id) {
+                    let until = match duration.map(|duration| Instant::now().checked_add(duration))
+                    {
+                        Some(None) => {
+                            return (
+                                Err(String::from("duration out of range")),
+                                AfterCommand::Wait,
+                            )
+                        }
+                        until => until.flatten(),
+                    };
+                    self.manual_boosts.insert(
+                        *fan_id,
+                        ManualBoost {
+                            boost: *boost,
+                            until,
+                        },
+                    );
+                    (
+                        Ok(format!("Fan #{fan_id} pinned to {boost}").into()),
+                        AfterCommand::Tick,
+                    )
+                } else {
+                    (Err(format!("Unknown fan #{fan_id}")), AfterCommand::Wait)
                 }
-
-                thread::sleep(milli_sec_dur);
             }
+            ControlCommand::ClearBoost {
+                fan_id: Some(fan_id),
+            } => {
+                if self.manual_boosts.remove(fan_id).is_some() {
+                    (
+                        Ok(format!("Fan #{fan_id} follows its curve again").into()),
+                        AfterCommand::Tick,
+                    )
+                } else {
+                    (
+                        Err(format!("Fan #{fan_id} is not pinned")),
+                        AfterCommand::Wait,
+                    )
+                }
+            }
+            ControlCommand::ClearBoost { fan_id: None } => {
+                self.manual_boosts.clear();
+                (
+                    Ok("Every fan follows its curve again".into()),
+                    AfterCommand::Tick,
+                )
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        let fans = self
+            .alien_dev_graph_infos
+            .iter()
+            .map(|info| FanStatus {
+                name: info.dev.name.to_string(),
+                fan_id: info.dev.fan_id,
+                sensor_id: info.dev.sen_id,
+                temp: get_temp(info.dev.sen_id),
+                rpm: get_fan_rpm(info.dev.fan_id),
+                boost: get_fan_boost(info.dev.fan_id),
+                health: info.health.health().to_string(),
+                pinned: self
+                    .manual_boosts
+                    .get(&info.dev.fan_id)
+                    .map(|manual| manual.boost),
+                clamped: info.clamped.map(|limit| limit.to_string()),
+                watchdog_fired: fired_count(info.dev.fan_id),
+            })
+            .collect();
+        Status {
+            power_mode: self.power_mode,
+            profile: self.active_profile.clone(),
+            emergency: self.emergency.is_active(),
+            fans,
+        }
+    }
+
+    /// Writes the state file if anything changed. After a clean shutdown the
+    /// power mode is back to the original, so it is left out.
+    fn save_state(&mut self, clean_shutdown: bool) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+        let current = State {
+            profile: self.active_profile.clone(),
+            pinned: self
+                .manual_boosts
+                .iter()
+                .map(|(fan_id, manual)| {
+                    let pin = Pin {
+                        boost: manual.boost,
+                        until: manual.until.map(state::instant_to_unix),
+                    };
+                    (*fan_id, pin)
+                })
+                .collect(),
+            original_power_mode: (!clean_shutdown).then_some(self.original_power_mode),
+            boosts: self
+                .alien_dev_graph_infos
+                .iter()
+                .map(|info| (info.dev.fan_id, info.last_fan_boost))
+                .collect(),
+        };
+        if self.saved_state.as_ref() == Some(&current) {
+            return;
+        }
+        if let Err(e) = state::save(path, &current) {
+            error!("Saving state to {}: {e}", path.display());
         }
+        // remembered even when saving failed, to not repeat the error every tick
+        self.saved_state = Some(current);
+    }
+
+    fn apply_active_profile(&mut self) {
+        let curves = &self.profiles[&self.active_profile];
+        for (info, curve) in self.alien_dev_graph_infos.iter_mut().zip(curves) {
+            info.curve = curve.clone();
+        }
+    }
+
+    fn notify_stopping(&self) {
+        if let Some(notifier) = &self.notifier {
+            notifier.stopping();
+        }
+    }
+
+    fn allows_power_mode(&self) -> bool {
+        self.policy
+            .as_ref()
+            .is_none_or(|policy| policy.allow_power_mode)
+    }
+
+    fn devices(&self) -> Devices {
+        self.alien_dev_graph_infos.each_ref().map(|info| info.dev)
+    }
+
+    fn has_fan(&self, fan_id: u8) -> bool {
+        self.alien_dev_graph_infos
+            .iter()
+            .any(|info| info.dev.fan_id == fan_id)
     }
 
     pub fn toggle_mode(&mut self) {
-        self.power_mode = toggle_power_mode() as u8;
+        self.power_mode = toggle_power_mode();
+    }
+
+    /// Goes by the mode the hardware reports, another acpi_call tool or
+    /// `awc mode --direct` may have changed it since the last tick
+    fn set_mode(&mut self, enabled: bool) {
+        let mode = if enabled { 0xab } else { 0 };
+        if get_power_mode() != mode as i64 {
+            info!(
+                "{} Power Mode",
+                if enabled { "Enabled" } else { "Disabled" }
+            );
+            set_power_mode(mode);
+        }
+        self.power_mode = mode;
+    }
+
+    /// Checks the critical thresholds, ahead of any curve or override, and
+    /// pins both fans to full boost for as long as the emergency lasts, even
+    /// when another tool writes over them in between
+    fn check_thermal_emergency(&mut self) -> bool {
+        if let Some(event) = self.emergency.update() {
+            emit(event);
+        }
+        if !self.emergency.is_active() {
+            return false;
+        }
+        for info in &mut self.alien_dev_graph_infos {
+            let fan_id = info.dev.fan_id;
+            if get_fan_boost(fan_id) != 255 {
+                let result = set_fan_boost(fan_id, 255);
+                info!(fan_id = fan_id, boost = 255, result = result; "Fan #{fan_id} boost 255/255 result: {result}");
+            }
+            info.last_fan_boost = 255;
+        }
+        true
+    }
+
+    /// Reports fans whose boost isn't what we last wrote, once until they
+    /// match again, then forgets what we wrote so this tick writes it again
+    fn check_conflicts(&mut self) {
+        for info in &mut self.alien_dev_graph_infos {
+            let read = get_fan_boost(info.dev.fan_id);
+            let conflicting = read != info.last_fan_boost;
+            if conflicting && !info.conflicting {
+                emit(Event::BoostConflict {
+                    fan_id: info.dev.fan_id,
+                    wrote: info.last_fan_boost,
+                    read,
+                    holders: acpi_call_holders(),
+                });
+            }
+            info.conflicting = conflicting;
+            info.last_fan_boost = read;
+        }
+    }
+
+    /// Updates every fan's health and returns whether any fan has failed, in
+    /// which case the remaining fans have to be run at full boost
+    fn check_fan_health(&mut self) -> bool {
+        let mut any_failed = false;
+        for info in &mut self.alien_dev_graph_infos {
+            let boost = get_fan_boost(info.dev.fan_id);
+            let rpm = get_fan_rpm(info.dev.fan_id);
+            let expected_rpm = info.curve.expected_rpm(boost);
+            if let Some(health) = info.health.update(boost, rpm, expected_rpm) {
+                emit(Event::FanHealthChanged {
+                    fan_id: info.dev.fan_id,
+                    health,
+                });
+            }
+            any_failed |= info.health.health() == FanHealth::Failed;
+        }
+        any_failed
     }
 }
 
-pub fn set_both_fan_boosts(value: u8) {
-    for dev in &ALIEN_DEVICES {
-        print!(
-            "Fan #{BOLD}{}{RESET}: {YELLOW}{}{RESET}/255 result: {}\n",
-            dev.fan_id,
-            value,
-            set_fan_boost(dev.fan_id, value)
-        );
+/// One line summary for `systemctl status`
+fn status_line(status: &Status) -> String {
+    let mut line = format!("Profile {}", status.profile);
+    if status.emergency {
+        line.push_str(", thermal emergency");
+    }
+    for fan in &status.fans {
+        line.push_str(&format!(
+            ", {} {}°C boost {} {} rpm",
+            fan.name, fan.temp, fan.boost, fan.rpm
+        ));
+    }
+    line
+}
+
+pub fn set_both_fan_boosts(devices: &Devices, value: u8) {
+    for dev in devices {
+        let fan_id = dev.fan_id;
+        let result = set_fan_boost(fan_id, value);
+        info!(fan_id = fan_id, boost = value, result = result; "Fan #{fan_id} boost {value}/255 result: {result}");
+    }
+}
+/// `set_both_fan_boosts` held to the administrator's limits, for the direct
+/// commands that bypass the controller
+pub fn set_both_fan_boosts_within(devices: &Devices, value: u8, policy: &Policy) {
+    let clock = chrono::Local::now().time();
+    for (device, dev) in devices.iter().enumerate() {
+        let fan_id = dev.fan_id;
+        let temp = get_temp(dev.sen_id) as u8;
+        let (boost, clamped) = policy.clamp(device, temp, value, clock);
+        if let Some(limit) = clamped {
+            warn!(fan_id = fan_id; "Fan #{fan_id} held to {boost} by the {limit}, asked for {value}");
+        }
+        let result = set_fan_boost(fan_id, boost);
+        info!(fan_id = fan_id, boost = boost, result = result; "Fan #{fan_id} boost {boost}/255 result: {result}");
     }
 }
-pub fn show_temps() {
-    for dev in &ALIEN_DEVICES {
-        print!(
-            "Sensor {} {BOLD}#{}{RESET}: {YELLOW}{}{RESET}\n",
+pub fn show_temps(devices: &Devices) {
+    for dev in devices {
+        println!(
+            "Sensor {} {}: {}",
             dev.name,
-            dev.sen_id,
-            get_temp(dev.sen_id)
+            bold(format!("#{}", dev.sen_id)),
+            yellow(get_temp(dev.sen_id))
         );
     }
 }
-pub fn show_fan_boosts() {
-    for dev in &ALIEN_DEVICES {
-        print!(
-            "Fan {BOLD}#{}{RESET}:\n boost: {YELLOW}{}{RESET}/255, rpm: {GREEN}{}{RESET}\n",
-            dev.fan_id,
-            get_fan_boost(dev.fan_id),
-            get_fan_rpm(dev.fan_id)
+pub fn show_fan_boosts(devices: &Devices) {
+    for dev in devices {
+        println!(
+            "Fan {}:\n boost: {}/255, rpm: {}",
+            bold(format!("#{}", dev.fan_id)),
+            yellow(get_fan_boost(dev.fan_id)),
+            green(get_fan_rpm(dev.fan_id))
         );
     }
 }
 
-fn get_fan_boost(fan_id: u8) -> u8 {
+pub(crate) fn get_fan_boost(fan_id: u8) -> u8 {
     run_main_command(0x14, 0xc, fan_id, 0) as u8
 }
 
-fn set_fan_boost(fan_id: u8, value: u8) -> i64 {
+pub(crate) fn set_fan_boost(fan_id: u8, value: u8) -> i64 {
     run_main_command(0x15, 2, fan_id, value)
 }
 
-fn get_fan_rpm(fan_id: u8) -> i64 {
+pub(crate) fn get_fan_rpm(fan_id: u8) -> i64 {
     run_main_command(0x14, 5, fan_id, 0)
 }
 
-fn get_temp(sen_id: u8) -> i64 {
+pub(crate) fn get_temp(sen_id: u8) -> i64 {
     run_main_command(0x14, 4, sen_id, 0)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CoOrdinates {
-    temp: u8,
-    fan_boost: u8,
+    pub temp: u8,
+    pub fan_boost: u8,
 }
 
-pub fn load_graph_from_string(s: &str) -> [AlienDevGraphInfo; 2] {
-    let mut lines = s.lines();
-    let line0 = lines.next().unwrap();
-    let line1 = lines.next().unwrap();
+/// Temperature to raw boost
+#[derive(Debug, Clone)]
+pub struct BoostCurve {
+    graph: Vec<CoOrdinates>,
+    graph_type: GraphType,
+    /// Only used to tell how fast the fan should be spinning
+    calibration: Option<Vec<CalibrationPoint>>,
+}
+
+impl BoostCurve {
+    pub fn new(graph: Vec<CoOrdinates>, graph_type: GraphType) -> Self {
+        Self {
+            graph,
+            graph_type,
+            calibration: None,
+        }
+    }
+
+    pub fn with_calibration(mut self, calibration: Option<Vec<CalibrationPoint>>) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    pub fn boost(&self, temp: u8) -> u8 {
+        match self.graph_type {
+            GraphType::Linear => get_boost_from_temp_linear(temp, &self.graph),
+            GraphType::Step => get_boost_from_temp_step(temp, &self.graph),
+        }
+    }
+}
 
-    return get_alien_dev_graph_info(line_to_coords(line0), line_to_coords(line1));
+pub fn get_coords_from_string(s: &str) -> Result<(Vec<CoOrdinates>, Vec<CoOrdinates>), String> {
+    let (line0, line1) = two_lines(s)?;
+    let cpu = line_to_coords(line0).map_err(|e| format!("line 1: {e}"))?;
+    let gpu = line_to_coords(line1).map_err(|e| format!("line 2: {e}"))?;
+    Ok((cpu, gpu))
 }
 
-pub fn get_coords_from_string(s: &str) -> (Vec<CoOrdinates>, Vec<CoOrdinates>) {
+/// The CPU and GPU lines of a graph or calibration file
+pub(crate) fn two_lines(s: &str) -> Result<(&str, &str), String> {
     let mut lines = s.lines();
-    let line0 = lines.next().unwrap();
-    let line1 = lines.next().unwrap();
-    return (line_to_coords(line0), line_to_coords(line1));
-}
-
-pub fn get_alien_dev_graph_info(
-    cpu_graph: Vec<CoOrdinates>,
-    gpu_graph: Vec<CoOrdinates>,
-) -> [AlienDevGraphInfo; 2] {
-    let now = SystemTime::now();
-
-    return [
-        AlienDevGraphInfo {
-            dev: &ALIEN_DEVICES[0],
-            graph: cpu_graph,
-            last_fan_boost: get_fan_boost(ALIEN_DEVICES[0].fan_id),
-            last_fan_rpm_recorded: LastFanRPMRecorded {
-                rpm: get_fan_rpm(ALIEN_DEVICES[0].fan_id),
-                ts: now,
-            },
-        },
-        AlienDevGraphInfo {
-            dev: &ALIEN_DEVICES[1],
-            graph: gpu_graph,
-            last_fan_boost: get_fan_boost(ALIEN_DEVICES[1].fan_id),
-            last_fan_rpm_recorded: LastFanRPMRecorded {
-                rpm: get_fan_rpm(ALIEN_DEVICES[1].fan_id),
-                ts: now,
-            },
+    match (lines.next(), lines.next()) {
+        (Some(line0), Some(line1)) => Ok((line0, line1)),
+        _ => Err(String::from("expected a CPU line and a GPU line")),
+    }
+}
+
+/// Parses one `(a b)` point
+pub(crate) fn parse_pair<A: FromStr, B: FromStr>(coord: &str) -> Result<(A, B), String> {
+    let c = coord.trim();
+    let inner = c
+        .strip_prefix('(')
+        .and_then(|c| c.strip_suffix(')'))
+        .ok_or_else(|| format!("{c:?} is not a (a b) pair"))?;
+    let mut s = inner.split_whitespace();
+    match (s.next(), s.next(), s.next()) {
+        (Some(a), Some(b), None) => match (a.parse(), b.parse()) {
+            (Ok(a), Ok(b)) => Ok((a, b)),
+            _ => Err(format!("{c:?} has a number out of range")),
         },
-    ];
+        _ => Err(format!("{c:?} is not a (a b) pair")),
+    }
 }
 
-pub fn load_graph(file_path: &str) -> [AlienDevGraphInfo; 2] {
-    let mut buf = String::with_capacity(1024);
-    OpenOptions::new()
-        .read(true)
-        .open(file_path)
-        .unwrap()
-        .read_to_string(&mut buf)
-        .unwrap();
+fn get_alien_dev_infos(devices: Devices, curves: [FanCurve; 2]) -> [AlienDevGraphInfo; 2] {
+    let [cpu, gpu] = devices;
+    let [cpu_curve, gpu_curve] = curves;
+    [
+        new_alien_dev_graph_info(cpu, cpu_curve),
+        new_alien_dev_graph_info(gpu, gpu_curve),
+    ]
+}
 
-    load_graph_from_string(&buf)
-
-    // let mut lines = buf.lines();
-    // let line0 = lines.next().unwrap();
-    // let line1 = lines.next().unwrap();
-
-    // let now = SystemTime::now();
-
-    // return [
-    //     AlienDevGraphInfo {
-    //         dev: &ALIEN_DEVICES[0],
-    //         graph: line_to_coords(line0),
-    //         last_fan_boost: get_fan_boost(ALIEN_DEVICES[0].fan_id),
-    //         last_fan_rpm_recorded: LastFanRPMRecorded {
-    //             rpm: get_fan_rpm(ALIEN_DEVICES[0].fan_id),
-    //             ts: now,
-    //         },
-    //     },
-    //     AlienDevGraphInfo {
-    //         dev: &ALIEN_DEVICES[1],
-    //         graph: line_to_coords(line1),
-    //         last_fan_boost: get_fan_boost(ALIEN_DEVICES[1].fan_id),
-    //         last_fan_rpm_recorded: LastFanRPMRecorded {
-    //             rpm: get_fan_rpm(ALIEN_DEVICES[1].fan_id),
-    //             ts: now,
-    //         },
-    //     },
-    // ];
-}
-
-fn line_to_coords(line: &str) -> Vec<CoOrdinates> {
+fn new_alien_dev_graph_info(dev: AlienDevInfo, curve: FanCurve) -> AlienDevGraphInfo {
+    AlienDevGraphInfo {
+        dev,
+        curve,
+        health: FanHealthMonitor::new(),
+        last_fan_boost: get_fan_boost(dev.fan_id),
+        last_fan_rpm_recorded: LastFanRPMRecorded::new(get_fan_rpm(dev.fan_id)),
+        clamped: None,
+        conflicting: false,
+        resume_boost: None,
+    }
+}
+
+pub(crate) fn line_to_coords(line: &str) -> Result<Vec<CoOrdinates>, String> {
     let mut v = Vec::<CoOrdinates>::with_capacity(32);
-    let mut last_temp = 0u8;
-    let mut first_time = true;
     for coord in line.split(',') {
-        let c = coord.trim();
-        let mut s = c[1..c.len() - 1].split_whitespace();
-        let temp = s.next().unwrap().parse().unwrap();
-        if !first_time && last_temp >= temp {
-            panic!("Temps must be ascending order!!!");
+        let (temp, fan_boost) = parse_pair(coord)?;
+        if let Some(last) = v.last() {
+            if last.temp >= temp {
+                return Err(String::from("Temps must be ascending order!!!"));
+            }
         }
-        let fan_boost = s.next().unwrap().parse().unwrap();
         v.push(CoOrdinates { temp, fan_boost });
-        last_temp = temp;
-        if first_time {
-            first_time = false;
-        }
     }
-    return v;
+    Ok(v)
 }
 
-fn get_boost_from_temp_step(temp: u8, coords: &Vec<CoOrdinates>) -> u8 {
+pub(crate) fn get_boost_from_temp_step(temp: u8, coords: &[CoOrdinates]) -> u8 {
     for coord in coords {
         if temp < coord.temp {
             return coord.fan_boost;
         }
     }
-    return coords.last().unwrap().fan_boost;
+    coords.last().unwrap().fan_boost
 }
-fn get_boost_from_temp_linear(temp: u8, coords: &Vec<CoOrdinates>) -> u8 {
-    for i in 0..coords.len() - 1 {
-        let a = &coords[i];
-        let b = &coords[i + 1];
+pub(crate) fn get_boost_from_temp_linear(temp: u8, coords: &[CoOrdinates]) -> u8 {
+    for w in coords.windows(2) {
+        let (a, b) = (&w[0], &w[1]);
         if temp >= a.temp && temp < b.temp {
-            let boost =
-                a.fan_boost + (temp - a.temp) * ((b.fan_boost - a.fan_boost) / (b.temp - a.temp));
-            return boost;
+            let t = (temp - a.temp) as i64;
+            let dt = (b.temp - a.temp) as i64;
+            let db = b.fan_boost as i64 - a.fan_boost as i64;
+            return (a.fan_boost as i64 + t * db / dt) as u8;
+        }
+    }
+    coords.last().unwrap().fan_boost
+}
+
+/// What the firmware reports it has
+#[derive(Debug, Clone, Default)]
+pub struct Probes {
+    pub sys_id: i64,
+    /// `(fan_id, sen_id)`, every fan with the sensor the firmware pairs it with
+    pub fans: Vec<(u8, u8)>,
+    pub sensors: Vec<u8>,
+    pub powers: Vec<u8>,
+}
+
+/// Walks the firmware's function table, an error when `/proc/acpi/call` is
+/// missing or can't be opened, as it can't without root
+pub fn probe() -> Result<Probes, String> {
+    if simulated::current().is_none() {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(ACPI_CALL_FPATH)
+            .map_err(|e| format!("{ACPI_CALL_FPATH}: {e}"))?;
+    }
+    let sys_id = run_main_command(0x1a, 2, 2, 0);
+
+    // the table lists the fans, then the sensors, then the power modes
+    let mut index = 0;
+    let mut func_id = run_main_command(0x14, 3, index, 0);
+    let mut fans = vec![];
+    while func_id > 0 && func_id < 0x100 || func_id > 0x130 {
+        fans.push(func_id as u8);
+        index += 1;
+        func_id = run_main_command(0x14, 3, index, 0);
+    }
+
+    let mut sensors = vec![];
+    while func_id > 0x100 && func_id < 0x1a0 {
+        let sen_id = func_id as u8;
+        if run_main_command(0x14, 4, sen_id, 0) > 0 {
+            sensors.push(sen_id);
         }
+        index += 1;
+        func_id = run_main_command(0x14, 3, index, 0);
     }
-    return coords.last().unwrap().fan_boost;
-}
-
-// fn probe_info() -> Probes {
-//     let sys_id = run_main_command(0x1a, 2, 2, 0);
-//     print!("Probe Allowed: {}\n", run_main_command(0x14, 2, 0, 0));
-//     print!("System Id: {}\n", sys_id);
-
-//     let mut f_index = 0;
-//     let mut func_id = 0;
-//     let mut fans = Vec::<AlienFanInfo>::with_capacity(8);
-//     let mut sensors = Vec::<AlienSenInfo>::with_capacity(8);
-//     let mut powers = Vec::<u8>::with_capacity(8);
-//     loop {
-//         let func_id = run_main_command(0x14, 3, f_index, 0);
-//         if func_id < 0x100 && func_id > 0 || func_id > 0x130 {
-//             fans.push(AlienFanInfo {
-//                 id: func_id as u8 & 0xff,
-//                 fan_type: 0xff,
-//             });
-//             f_index += 1;
-//         } else {
-//             break;
-//         }
-//     }
-
-//     print!(
-//         "{f_index} Fans Detected, Last Reply {func_id}:\n{:?}\n",
-//         fans
-//     );
-
-//     let first_sensor_index = f_index;
-//     loop {
-//         let s_index = (f_index - first_sensor_index) as usize;
-//         let res = run_main_command(0x14, 4, func_id as u8, 0);
-//         if res > 0 {
-//             let name = format!("Sensor #{s_index}");
-//             sensors.push(AlienSenInfo {
-//                 id: func_id as u8,
-//                 sen_type: 1,
-//             });
-//             f_index += 1;
-//         }
-//         func_id = run_main_command(0x14, 3, f_index, 0);
-//         if !(func_id > 0x100 && func_id < 0x1A0) {
-//             break;
-//         }
-//     }
-//     print!(
-//         "{} Sensors Detected, Last Reply {func_id}:\n {:?}\n",
-//         sensors.len(),
-//         sensors
-//     );
-//     if (func_id > 0) {
-//         loop {
-//             powers.push(func_id as u8 & 0xff);
-//             f_index += 1;
-//             func_id = run_main_command(0x14, 3, f_index, 0);
-//             if func_id <= 0 {
-//                 break;
-//             }
-//         }
-//         print!(
-//             "{} Power Modes Detected, Last Reply {func_id}:\n {:?}\n",
-//             powers.len(),
-//             powers
-//         );
-//     }
-
-//     for j in 0..fans.len() {
-//         let index = run_main_command(0x13, 2, fans[j].id, 0);
-//         let sensor = AlienSenInfo {
-//             id: index as u8,
-//             sen_type: 1,
-//         };
-//         for i in 0..sensors.len() {
-//             if sensors[i].id == sensor.id && sensors[i].sen_type == sensor.sen_type {
-//                 fans[j].fan_type = i as u8;
-//                 break;
-//             }
-//         }
-//     }
-//     print!("Fans: {:?}\n\n\n\n\n", fans);
-//     return Probes {
-//         sensors,
-//         fans,
-//         powers,
-//         sys_id,
-//     };
-// }
-
-pub fn show_all_info() {
+
+    let mut powers = vec![];
+    while func_id > 0 {
+        powers.push(func_id as u8);
+        index += 1;
+        func_id = run_main_command(0x14, 3, index, 0);
+    }
+
+    let fans = fans
+        .into_iter()
+        .map(|fan_id| (fan_id, run_main_command(0x13, 2, fan_id, 0) as u8))
+        .collect();
+    Ok(Probes {
+        sys_id,
+        fans,
+        sensors,
+        powers,
+    })
+}
+
+pub fn show_all_info(devices: &Devices) {
     let mode = get_power_mode();
-    print!("Power Mode: {mode}\n");
-    for dev in &ALIEN_DEVICES {
+    println!("Power Mode: {mode}");
+    for dev in devices {
         let temp = get_temp(dev.sen_id);
         let rpm = get_fan_rpm(dev.fan_id);
         let boost = get_fan_boost(dev.fan_id);
 
-        print!("{}: \n", dev.name);
-        print!(
-            " Sensor {BOLD}#{}{RESET} Temp: {YELLOW}{}{RESET}\n",
-            dev.sen_id, temp
+        println!("{}: ", dev.name);
+        println!(
+            " Sensor {} Temp: {}",
+            bold(format!("#{}", dev.sen_id)),
+            yellow(temp)
         );
-        print!(
-            " Fan {BOLD}#{}{RESET} boost: {YELLOW}{}{RESET}/255 rpm: {GREEN}{}{RESET}\n",
-            dev.fan_id, boost, rpm
+        println!(
+            " Fan {} boost: {}/255 rpm: {}",
+            bold(format!("#{}", dev.fan_id)),
+            yellow(boost),
+            green(rpm)
         );
     }
 }
 
 pub fn toggle_power_mode() -> u8 {
     if get_power_mode() == 0 {
-        print!("{BOLD}{GREEN}Enabled Power Mode\n{RESET}");
+        info!("Enabled Power Mode");
         set_power_mode(0xab);
         0xab
     } else {
-        print!("{BOLD}Disabled Power Mode\n{RESET}");
+        info!("Disabled Power Mode");
         set_power_mode(0);
         0
     }
 }
 
-fn get_power_mode() -> i64 {
+pub(crate) fn get_power_mode() -> i64 {
     run_main_command(0x14, 0xb, 0, 0)
 }
 
@@ -469,7 +1069,7 @@ fn disable_gmode() -> i64 {
     set_power_mode(0)
 }
 
-fn set_power_mode(mode: u8) -> i64 {
+pub(crate) fn set_power_mode(mode: u8) -> i64 {
     run_main_command(0x15, 1, mode, 0)
 }
 
@@ -484,7 +1084,7 @@ fn run_command(cmd: &str) -> String {
         .truncate(true)
         .open(ACPI_CALL_FPATH)
         .unwrap();
-    f.write(cmd.as_bytes()).unwrap();
+    f.write_all(cmd.as_bytes()).unwrap();
     let mut s = String::with_capacity(32);
     f.read_to_string(&mut s);
     s.pop();
@@ -492,8 +1092,195 @@ fn run_command(cmd: &str) -> String {
 }
 
 fn run_main_command(cmd: u8, sub: u8, arg0: u8, arg1: u8) -> i64 {
+    if let Some(backend) = simulated::current() {
+        return backend.call(cmd, sub, arg0, arg1);
+    }
     let s = format!("\\_SB.AMW3.WMAX 0 {cmd} {{ {sub}, {arg0}, {arg1}, 0 }}");
     let result = run_command(&s);
-    let res = i64::from_str_radix(&result[2..], 16).unwrap();
-    res
+
+    i64::from_str_radix(&result[2..], 16).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulated::{drive, test_controller, with_simulated};
+
+    fn graph(s: &str) -> Vec<CoOrdinates> {
+        line_to_coords(s).unwrap()
+    }
+
+    #[test]
+    fn boost_curves_interpolate_or_step() {
+        let linear = BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Linear);
+        let step = BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Step);
+
+        assert_eq!(linear.boost(30), 100);
+        assert_eq!(step.boost(30), 200);
+        assert_eq!(linear.boost(90), 200);
+
+        // shallow segments still climb
+        let shallow = BoostCurve::new(graph("(0 0), (100 50)"), GraphType::Linear);
+        assert_eq!(shallow.boost(50), 25);
+        assert_eq!(shallow.boost(99), 49);
+    }
+
+    #[test]
+    fn each_fan_follows_its_own_curve_type() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 30);
+            sim.set_temp(6, 30);
+            let curves = [
+                FanCurve::Boost(BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Linear)),
+                FanCurve::Boost(BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Step)),
+            ];
+
+            let mut controller = Controller::new(
+                ALIEN_DEVICES,
+                curves,
+                ThermalEmergency::new(vec![], Duration::ZERO),
+            );
+
+            let boosts = drive(&mut controller, |handle| {
+                handle.send(ControlCommand::Status).unwrap();
+                (sim.boost(50), sim.boost(51))
+            });
+
+            assert_eq!(boosts, (100, 200));
+        });
+    }
+
+    #[test]
+    fn fans_follow_the_sensors_they_are_given() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 30);
+            sim.set_temp(6, 0);
+            let curve =
+                FanCurve::Boost(BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Linear));
+            let devices = devices_from_ids((6, 50), (1, 51));
+            let mut controller = Controller::new(
+                devices,
+                [curve.clone(), curve],
+                ThermalEmergency::new(vec![], Duration::ZERO),
+            );
+
+            let boosts = drive(&mut controller, |handle| {
+                handle.send(ControlCommand::Status).unwrap();
+                (sim.boost(50), sim.boost(51))
+            });
+
+            assert_eq!(boosts, (0, 100));
+            assert_eq!(device_ids(&devices), ((6, 50), (1, 51)));
+        });
+    }
+
+    #[test]
+    fn pins_beat_curves_and_emergencies_beat_pins() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 30);
+            sim.set_temp(6, 30);
+            let curve =
+                FanCurve::Boost(BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Linear));
+            let emergency = ThermalEmergency::new(vec!["1=90".parse().unwrap()], Duration::ZERO);
+            let mut controller = Controller::new(ALIEN_DEVICES, [curve.clone(), curve], emergency);
+
+            let (pinned, emergency) = drive(&mut controller, |handle| {
+                let forever = handle.send(ControlCommand::SetBoost {
+                    fan_id: 50,
+                    boost: 80,
+                    duration: Some(Duration::MAX),
+                });
+                assert_eq!(forever.unwrap_err(), "duration out of range");
+                handle
+                    .send(ControlCommand::SetBoost {
+                        fan_id: 50,
+                        boost: 80,
+                        duration: None,
+                    })
+                    .unwrap();
+                let pinned = (sim.boost(50), sim.boost(51));
+                sim.set_temp(1, 95);
+                handle.send(ControlCommand::Next).unwrap();
+                (pinned, (sim.boost(50), sim.boost(51)))
+            });
+
+            assert_eq!(pinned, (80, 100));
+            assert_eq!(emergency, (255, 255));
+        });
+    }
+
+    #[test]
+    fn an_emergency_keeps_power_mode_off_and_the_fans_at_full_boost() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 95);
+            let emergency = ThermalEmergency::new(vec!["1=90".parse().unwrap()], Duration::ZERO);
+            let curve =
+                FanCurve::Boost(BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Linear));
+            let mut controller = Controller::new(ALIEN_DEVICES, [curve.clone(), curve], emergency);
+
+            let (toggled, set, power_mode, boosts) = drive(&mut controller, |handle| {
+                let toggled = handle.send(ControlCommand::TogglePowerMode);
+                let set = handle.send(ControlCommand::SetPowerMode(true));
+                let power_mode = sim.power_mode();
+                // another tool turning a fan down mid emergency
+                set_fan_boost(50, 10);
+                handle.send(ControlCommand::Next).unwrap();
+                (toggled, set, power_mode, (sim.boost(50), sim.boost(51)))
+            });
+
+            assert_eq!(toggled.unwrap_err(), POWER_MODE_EMERGENCY);
+            assert_eq!(set.unwrap_err(), POWER_MODE_EMERGENCY);
+            assert_eq!(power_mode, 0);
+            assert_eq!(boosts, (255, 255));
+        });
+    }
+
+    #[test]
+    fn power_mode_set_behind_our_back_is_still_turned_off() {
+        with_simulated(|sim| {
+            let emergency = ThermalEmergency::new(vec!["1=90".parse().unwrap()], Duration::ZERO);
+            let curve =
+                FanCurve::Boost(BoostCurve::new(graph("(0 0), (60 200)"), GraphType::Linear));
+            let mut controller = Controller::new(ALIEN_DEVICES, [curve.clone(), curve], emergency);
+
+            let (asked, emergency) = drive(&mut controller, |handle| {
+                sim.set_power_mode(0xab);
+                handle.send(ControlCommand::SetPowerMode(false)).unwrap();
+                let asked = sim.power_mode();
+                sim.set_power_mode(0xab);
+                sim.set_temp(1, 95);
+                handle.send(ControlCommand::Next).unwrap();
+                (asked, sim.power_mode())
+            });
+
+            assert_eq!(asked, 0);
+            assert_eq!(emergency, 0);
+        });
+    }
+
+    #[test]
+    fn policy_clamps_curves_and_denies_power_mode() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 30);
+            sim.set_temp(6, 30);
+            sim.set_power_mode(0xab);
+            let mut controller = test_controller();
+            let policy =
+                "{ floor: { cpu: [{ temp: 0, fan_boost: 150 }] }, allow_power_mode: false }";
+            controller.set_policy(json5::from_str(policy).unwrap());
+            let (status, toggled, power_mode) = drive(&mut controller, |handle| {
+                let Ok(Reply::Status(status)) = handle.send(ControlCommand::Status) else {
+                    panic!("no status");
+                };
+                let toggled = handle.send(ControlCommand::TogglePowerMode);
+                (status, toggled, sim.power_mode())
+            });
+
+            assert_eq!((status.fans[0].boost, status.fans[1].boost), (150, 100));
+            assert_eq!(status.fans[0].clamped.as_deref(), Some("safety floor"));
+            assert_eq!(status.fans[1].clamped, None);
+            assert_eq!(toggled.unwrap_err(), POWER_MODE_DENIED);
+            assert_eq!((status.power_mode, power_mode), (0, 0));
+        });
+    }
 }
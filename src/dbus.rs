@@ -0,0 +1,290 @@
+use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+
+use clap::ValueEnum;
+use futures_lite::future::block_on;
+use zbus::{
+    blocking::{connection, Connection},
+    fdo, interface,
+    object_server::SignalEmitter,
+};
+
+use crate::{
+    control::{ControlCommand, Reply, SharedHandle, Status},
+    events::Event,
+    feed::{self, FeedItem},
+};
+
+pub const BUS_NAME: &str = "org.awc.FanControl";
+pub const OBJECT_PATH: &str = "/org/awc/FanControl";
+
+/// Which bus `org.awc.FanControl` is published on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Bus {
+    System,
+    Session,
+    /// Don't publish it at all
+    Off,
+}
+
+/// `org.awc.FanControl`, every call goes through the running controller.
+/// Maps are keyed by fan name, `CPU` and `GPU`.
+pub struct FanControl {
+    handle: SharedHandle,
+}
+
+impl FanControl {
+    fn status(&self) -> fdo::Result<Status> {
+        match self.send(ControlCommand::Status)? {
+            Reply::Status(status) => Ok(status),
+            Reply::Message(message) => Err(fdo::Error::Failed(message)),
+        }
+    }
+
+    fn send(&self, command: ControlCommand) -> fdo::Result<Reply> {
+        self.handle.send(command).map_err(fdo::Error::Failed)
+    }
+}
+
+#[interface(name = "org.awc.FanControl")]
+impl FanControl {
+    #[zbus(property)]
+    fn temperatures(&self) -> fdo::Result<HashMap<String, i64>> {
+        let status = self.status()?;
+        Ok(status.fans.into_iter().map(|f| (f.name, f.temp)).collect())
+    }
+
+    #[zbus(property)]
+    fn fan_rpms(&self) -> fdo::Result<HashMap<String, i64>> {
+        let status = self.status()?;
+        Ok(status.fans.into_iter().map(|f| (f.name, f.rpm)).collect())
+    }
+
+    #[zbus(property)]
+    fn boosts(&self) -> fdo::Result<HashMap<String, u8>> {
+        let status = self.status()?;
+        Ok(status.fans.into_iter().map(|f| (f.name, f.boost)).collect())
+    }
+
+    #[zbus(property)]
+    fn power_mode(&self) -> fdo::Result<u8> {
+        Ok(self.status()?.power_mode)
+    }
+
+    #[zbus(property)]
+    fn active_profile(&self) -> fdo::Result<String> {
+        Ok(self.status()?.profile)
+    }
+
+    fn set_profile(&self, name: String) -> fdo::Result<String> {
+        Ok(self.send(ControlCommand::SetProfile(name))?.to_string())
+    }
+
+    fn set_power_mode(&self, enabled: bool) -> fdo::Result<String> {
+        Ok(self
+            .send(ControlCommand::SetPowerMode(enabled))?
+            .to_string())
+    }
+
+    /// Pins `fan` to `boost` for `seconds`, then hands it back to its curve
+    fn request_boost(&self, fan: u8, boost: u8, seconds: u64) -> fdo::Result<String> {
+        let command = ControlCommand::SetBoost {
+            fan_id: fan,
+            boost,
+            duration: Some(Duration::from_secs(seconds)),
+        };
+        Ok(self.send(command)?.to_string())
+    }
+
+    #[zbus(signal)]
+    async fn thermal_emergency(
+        emitter: &SignalEmitter<'_>,
+        sensor: u8,
+        temp: i64,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn thermal_emergency_cleared(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn fan_health_changed(
+        emitter: &SignalEmitter<'_>,
+        fan: u8,
+        health: String,
+    ) -> zbus::Result<()>;
+}
+
+/// `org.awc.FanControl` on the bus, taken off it when dropped
+pub struct Service {
+    _conn: Connection,
+    /// The signal thread only holds a weak reference, it stops with us
+    _emitter: Arc<SignalEmitter<'static>>,
+}
+
+/// Publishes `org.awc.FanControl` on `bus`, `None` for [`Bus::Off`]. The
+/// service lives as long as the returned [`Service`].
+pub fn serve(bus: Bus, handle: SharedHandle) -> zbus::Result<Option<Service>> {
+    let builder = match bus {
+        Bus::System => connection::Builder::system()?,
+        Bus::Session => connection::Builder::session()?,
+        Bus::Off => return Ok(None),
+    };
+    serve_with(builder.name(BUS_NAME)?, handle).map(Some)
+}
+
+fn serve_with(builder: connection::Builder<'_>, handle: SharedHandle) -> zbus::Result<Service> {
+    let conn = builder
+        .serve_at(OBJECT_PATH, FanControl { handle })?
+        .build()?;
+
+    let events = feed::subscribe(false);
+    let emitter = Arc::new(SignalEmitter::new(conn.inner(), OBJECT_PATH)?.into_owned());
+    let weak = Arc::downgrade(&emitter);
+    thread::spawn(move || {
+        for item in events {
+            let FeedItem::Event(event) = item else {
+                continue;
+            };
+            // the service is gone, dropping `events` unsubscribes
+            let Some(emitter) = weak.upgrade() else {
+                return;
+            };
+            let result = block_on(async {
+                match event {
+                    Event::ThermalEmergency { sensor, temp } => {
+                        FanControl::thermal_emergency(&emitter, sensor, temp).await
+                    }
+                    Event::ThermalEmergencyCleared => {
+                        FanControl::thermal_emergency_cleared(&emitter).await
+                    }
+                    Event::FanHealthChanged { fan_id, health } => {
+                        FanControl::fan_health_changed(&emitter, fan_id, health.to_string()).await
+                    }
+                    Event::ProfileChanged { .. }
+                    | Event::WatchdogFired { .. }
+                    | Event::BoostConflict { .. }
+                    | Event::BoostClamped { .. } => Ok(()),
+                }
+            });
+            if let Err(e) = result {
+                error!("D-Bus signal: {e}");
+            }
+        }
+    });
+    Ok(Service {
+        _conn: conn,
+        _emitter: emitter,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::mpsc,
+    };
+
+    use zbus::{
+        blocking::{proxy, Proxy},
+        proxy::CacheProperties,
+    };
+
+    use super::*;
+    use crate::{
+        events,
+        simulated::{drive, test_controller, with_simulated},
+    };
+
+    /// A private session bus, killed on drop
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[test]
+    fn fan_control_on_a_private_bus() {
+        let Some(bus) = PrivateBus::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        with_simulated(|sim| {
+            let shared = SharedHandle::default();
+            let mut controller = test_controller();
+            drive(&mut controller, |handle| {
+                shared.set(Some(handle.clone()));
+                let builder = connection::Builder::address(bus.address.as_str())
+                    .unwrap()
+                    .name(BUS_NAME)
+                    .unwrap();
+                let _service = serve_with(builder, shared.clone()).unwrap();
+                let client = connection::Builder::address(bus.address.as_str())
+                    .unwrap()
+                    .build()
+                    .unwrap();
+                let proxy: Proxy = proxy::Builder::new(&client)
+                    .destination(BUS_NAME)
+                    .unwrap()
+                    .path(OBJECT_PATH)
+                    .unwrap()
+                    .interface(BUS_NAME)
+                    .unwrap()
+                    .cache_properties(CacheProperties::No)
+                    .build()
+                    .unwrap();
+
+                sim.set_temp(1, 30);
+                let temps: HashMap<String, i64> = proxy.get_property("Temperatures").unwrap();
+                assert_eq!(temps["CPU"], 30);
+                let profile: String = proxy.get_property("ActiveProfile").unwrap();
+                assert_eq!(profile, "default");
+
+                let _: String = proxy.call("RequestBoost", &(50u8, 210u8, 60u64)).unwrap();
+                assert_eq!(sim.boost(50), 210);
+                let boosts: HashMap<String, u8> = proxy.get_property("Boosts").unwrap();
+                assert_eq!(boosts["CPU"], 210);
+                let unknown: zbus::Result<String> = proxy.call("SetProfile", &("silent",));
+                assert!(unknown.is_err());
+
+                let (tx, rx) = mpsc::channel();
+                let mut signals = proxy.receive_signal("ThermalEmergency").unwrap();
+                thread::spawn(move || {
+                    if let Some(message) = signals.next() {
+                        let _ = tx.send(message.body().deserialize::<(u8, i64)>().unwrap());
+                    }
+                });
+                events::emit(Event::ThermalEmergency {
+                    sensor: 1,
+                    temp: 99,
+                });
+                assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), (1, 99));
+                shared.set(None);
+            });
+        });
+    }
+}
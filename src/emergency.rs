@@ -0,0 +1,183 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{controller::get_temp, events::Event};
+
+/// `SENSOR=CRITICAL[:RELEASE]`, release defaults to 10 degrees below critical
+#[derive(Debug, Clone, Copy)]
+pub struct CriticalThreshold {
+    pub sensor: u8,
+    pub critical: u8,
+    pub release: u8,
+}
+
+impl FromStr for CriticalThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sensor, temps) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected SENSOR=CRITICAL[:RELEASE], got {s}"))?;
+        let (critical, release) = match temps.split_once(':') {
+            Some((critical, release)) => (critical, Some(release)),
+            None => (temps, None),
+        };
+        let sensor = sensor.trim().parse().map_err(|e| format!("sensor: {e}"))?;
+        let critical: u8 = critical
+            .trim()
+            .parse()
+            .map_err(|e| format!("critical: {e}"))?;
+        let release = match release {
+            Some(release) => release
+                .trim()
+                .parse()
+                .map_err(|e| format!("release: {e}"))?,
+            None => critical.saturating_sub(10),
+        };
+        if release > critical {
+            return Err(format!("release {release} is above critical {critical}"));
+        }
+        Ok(Self {
+            sensor,
+            critical,
+            release,
+        })
+    }
+}
+
+/// Tracks whether any sensor is past its critical temperature. Once tripped it
+/// only clears after every sensor has stayed below its release temperature
+/// for `release_hold`.
+#[derive(Debug)]
+pub struct ThermalEmergency {
+    thresholds: Vec<CriticalThreshold>,
+    release_hold: Duration,
+    active: bool,
+    below_since: Option<Instant>,
+}
+
+impl ThermalEmergency {
+    pub fn new(thresholds: Vec<CriticalThreshold>, release_hold: Duration) -> Self {
+        Self {
+            thresholds,
+            release_hold,
+            active: false,
+            below_since: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Reads the sensors and returns an event when the state flips
+    pub fn update(&mut self) -> Option<Event> {
+        self.update_at(Instant::now())
+    }
+
+    fn update_at(&mut self, now: Instant) -> Option<Event> {
+        let mut all_released = true;
+        for threshold in &self.thresholds {
+            let temp = get_temp(threshold.sensor);
+            if temp >= threshold.critical as i64 {
+                self.below_since = None;
+                if !self.active {
+                    self.active = true;
+                    return Some(Event::ThermalEmergency {
+                        sensor: threshold.sensor,
+                        temp,
+                    });
+                }
+                return None;
+            }
+            if temp >= threshold.release as i64 {
+                all_released = false;
+            }
+        }
+
+        if !self.active {
+            return None;
+        }
+        if !all_released {
+            self.below_since = None;
+            return None;
+        }
+        let below_since = *self.below_since.get_or_insert(now);
+        if now.duration_since(below_since) < self.release_hold {
+            return None;
+        }
+        self.active = false;
+        self.below_since = None;
+        Some(Event::ThermalEmergencyCleared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulated::with_simulated;
+
+    #[test]
+    fn thresholds_parse_with_a_default_release() {
+        let threshold: CriticalThreshold = "1=90".parse().unwrap();
+        assert_eq!(
+            (threshold.sensor, threshold.critical, threshold.release),
+            (1, 90, 80)
+        );
+        let threshold: CriticalThreshold = " 6 = 95:85".parse().unwrap();
+        assert_eq!(
+            (threshold.sensor, threshold.critical, threshold.release),
+            (6, 95, 85)
+        );
+        let low: CriticalThreshold = "1=5".parse().unwrap();
+        assert_eq!(low.release, 0);
+
+        assert!("90".parse::<CriticalThreshold>().is_err());
+        assert!("1=300".parse::<CriticalThreshold>().is_err());
+        assert!("x=90".parse::<CriticalThreshold>().is_err());
+        let above = "1=90:95".parse::<CriticalThreshold>().unwrap_err();
+        assert_eq!(above, "release 95 is above critical 90");
+    }
+
+    #[test]
+    fn clears_only_after_the_release_hold_and_a_retrip_starts_it_over() {
+        with_simulated(|sim| {
+            let hold = Duration::from_secs(30);
+            let mut emergency = ThermalEmergency::new(vec!["1=90".parse().unwrap()], hold);
+            let start = Instant::now();
+            let at = |secs| start + Duration::from_secs(secs);
+
+            sim.set_temp(1, 95);
+            assert!(matches!(
+                emergency.update_at(at(0)),
+                Some(Event::ThermalEmergency {
+                    sensor: 1,
+                    temp: 95
+                })
+            ));
+            assert!(emergency.update_at(at(1)).is_none());
+
+            // below critical but above release doesn't start the hold
+            sim.set_temp(1, 85);
+            assert!(emergency.update_at(at(2)).is_none());
+            sim.set_temp(1, 70);
+            assert!(emergency.update_at(at(10)).is_none());
+            assert!(emergency.update_at(at(39)).is_none());
+            assert!(emergency.is_active());
+
+            // tripping again starts the hold over
+            sim.set_temp(1, 92);
+            assert!(emergency.update_at(at(39)).is_none());
+            sim.set_temp(1, 70);
+            assert!(emergency.update_at(at(41)).is_none());
+            assert!(emergency.update_at(at(70)).is_none());
+            assert!(matches!(
+                emergency.update_at(at(71)),
+                Some(Event::ThermalEmergencyCleared)
+            ));
+            assert!(!emergency.is_active());
+        });
+    }
+}
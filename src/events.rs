@@ -0,0 +1,260 @@
+use std::{
+    fmt,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    thread,
+};
+
+use serde::Serialize;
+
+use crate::{
+    fan_health::FanHealth,
+    feed,
+    log::{self, Level},
+    policy::Limit,
+};
+
+/// Shell command run for every event, with the details in `AWC_*` env vars
+static HOOK: OnceLock<String> = OnceLock::new();
+/// Whether critical events also pop up a desktop notification
+static NOTIFY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    #[serde(rename = "fan-health")]
+    FanHealthChanged {
+        fan_id: u8,
+        health: FanHealth,
+    },
+    ThermalEmergency {
+        sensor: u8,
+        temp: i64,
+    },
+    ThermalEmergencyCleared,
+    ProfileChanged {
+        profile: String,
+    },
+    /// The watchdog kicked a fan that was stuck
+    WatchdogFired {
+        fan_id: u8,
+    },
+    /// Something else wrote a fan boost, `holders` have `/proc/acpi/call` open
+    BoostConflict {
+        fan_id: u8,
+        wrote: u8,
+        read: u8,
+        holders: Vec<String>,
+    },
+    /// The policy held a fan's boost to `boost` instead of the `wanted` one
+    BoostClamped {
+        fan_id: u8,
+        wanted: u8,
+        boost: u8,
+        limit: Limit,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::FanHealthChanged { .. } => "fan-health",
+            Event::ThermalEmergency { .. } => "thermal-emergency",
+            Event::ThermalEmergencyCleared => "thermal-emergency-cleared",
+            Event::ProfileChanged { .. } => "profile-changed",
+            Event::WatchdogFired { .. } => "watchdog-fired",
+            Event::BoostConflict { .. } => "boost-conflict",
+            Event::BoostClamped { .. } => "boost-clamped",
+        }
+    }
+
+    pub fn is_critical(&self) -> bool {
+        match self {
+            Event::FanHealthChanged { health, .. } => *health != FanHealth::Ok,
+            Event::ThermalEmergency { .. } | Event::BoostConflict { .. } => true,
+            Event::ThermalEmergencyCleared
+            | Event::ProfileChanged { .. }
+            | Event::WatchdogFired { .. }
+            | Event::BoostClamped { .. } => false,
+        }
+    }
+
+    /// The details, named like the fields of the other log lines
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Event::FanHealthChanged { fan_id, health } => vec![
+                ("fan_id", fan_id.to_string()),
+                ("health", health.to_string()),
+            ],
+            Event::ThermalEmergency { sensor, temp } => vec![
+                ("sensor_id", sensor.to_string()),
+                ("temp", temp.to_string()),
+            ],
+            Event::ThermalEmergencyCleared => vec![],
+            Event::ProfileChanged { profile } => vec![("profile", profile.clone())],
+            Event::WatchdogFired { fan_id } => vec![("fan_id", fan_id.to_string())],
+            Event::BoostConflict {
+                fan_id,
+                wrote,
+                read,
+                holders,
+            } => vec![
+                ("fan_id", fan_id.to_string()),
+                ("wrote", wrote.to_string()),
+                ("read", read.to_string()),
+                ("holders", holders.join(", ")),
+            ],
+            Event::BoostClamped {
+                fan_id,
+                wanted,
+                boost,
+                limit,
+            } => vec![
+                ("fan_id", fan_id.to_string()),
+                ("wanted", wanted.to_string()),
+                ("boost", boost.to_string()),
+                ("limit", limit.to_string()),
+            ],
+        }
+    }
+
+    /// The details for the hook, `AWC_FAN`, `AWC_SENSOR` and `AWC_<FIELD>`
+    fn env(&self) -> Vec<(String, String)> {
+        self.fields()
+            .into_iter()
+            .map(|(field, value)| {
+                let var = match field {
+                    "fan_id" => String::from("AWC_FAN"),
+                    "sensor_id" => String::from("AWC_SENSOR"),
+                    field => format!("AWC_{}", field.to_uppercase()),
+                };
+                (var, value)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::FanHealthChanged { fan_id, health } => {
+                write!(f, "Fan #{fan_id} health is now {health}")
+            }
+            Event::ThermalEmergency { sensor, temp } => write!(
+                f,
+                "Sensor #{sensor} is at {temp}, thermal emergency, all fans to max"
+            ),
+            Event::ThermalEmergencyCleared => {
+                write!(f, "Thermal emergency cleared, back to the fan curves")
+            }
+            Event::ProfileChanged { profile } => write!(f, "Switched to profile {profile}"),
+            Event::WatchdogFired { fan_id } => {
+                write!(f, "Fan #{fan_id} was stuck, the watchdog kicked it")
+            }
+            Event::BoostConflict {
+                fan_id,
+                wrote,
+                read,
+                holders,
+            } => {
+                write!(
+                    f,
+                    "Fan #{fan_id} boost is {read} but awc wrote {wrote}, another tool is setting it"
+                )?;
+                if !holders.is_empty() {
+                    write!(f, " ({} have /proc/acpi/call open)", holders.join(", "))?;
+                }
+                Ok(())
+            }
+            Event::BoostClamped {
+                fan_id,
+                wanted,
+                boost,
+                limit,
+            } => write!(
+                f,
+                "Fan #{fan_id} held to boost {boost} by the {limit}, its curve wanted {wanted}"
+            ),
+        }
+    }
+}
+
+pub fn set_hook(cmd: String) {
+    let _ = HOOK.set(cmd);
+}
+
+pub fn set_notify(notify: bool) {
+    NOTIFY.store(notify, Ordering::SeqCst);
+}
+
+/// Logs the event, then hands it to the hook and the desktop notifier
+pub fn emit(event: Event) {
+    let level = if event.is_critical() {
+        Level::Error
+    } else {
+        Level::Info
+    };
+    let mut fields = event.fields();
+    fields.push(("event", event.name().to_string()));
+    log::write(level, module_path!(), &fields, format_args!("{event}"));
+
+    if let Some(hook) = HOOK.get() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(hook)
+            .env("AWC_EVENT", event.name())
+            .env("AWC_MESSAGE", event.to_string())
+            .envs(event.env());
+        spawn_detached(cmd);
+    }
+
+    if event.is_critical() && NOTIFY.load(Ordering::SeqCst) {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["--urgency=critical", "awc", &event.to_string()]);
+        spawn_detached(cmd);
+    }
+
+    feed::publish_event(event);
+}
+
+/// Runs `cmd` without blocking the caller, reaping it from a helper thread
+fn spawn_detached(mut cmd: Command) {
+    match cmd.spawn() {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => error!("Failed to run {:?}: {e}", cmd.get_program()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_use_the_tick_field_names_and_hooks_keep_theirs() {
+        let event = Event::ThermalEmergency {
+            sensor: 1,
+            temp: 99,
+        };
+        assert_eq!(
+            event.fields(),
+            [
+                ("sensor_id", String::from("1")),
+                ("temp", String::from("99"))
+            ]
+        );
+        assert_eq!(
+            event.env(),
+            [
+                (String::from("AWC_SENSOR"), String::from("1")),
+                (String::from("AWC_TEMP"), String::from("99"))
+            ]
+        );
+        let event = Event::WatchdogFired { fan_id: 50 };
+        assert_eq!(event.env()[0].0, "AWC_FAN");
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Boost above which a fan is expected to be spinning
+const HIGH_BOOST: u8 = 128;
+/// Ticks a new classification has to hold before it is reported
+const SETTLE_TICKS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FanHealth {
+    Ok,
+    /// Spinning, but far below what the calibration table expects
+    Degraded,
+    /// Not spinning at all although it is asked to
+    Failed,
+}
+
+impl fmt::Display for FanHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanHealth::Ok => write!(f, "ok"),
+            FanHealth::Degraded => write!(f, "degraded"),
+            FanHealth::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Classifies a fan every tick from its boost and rpm readback. Fans take a
+/// few seconds to spin up, so a classification only sticks once it has been
+/// seen for `SETTLE_TICKS` ticks in a row.
+#[derive(Debug)]
+pub struct FanHealthMonitor {
+    health: FanHealth,
+    candidate: FanHealth,
+    candidate_ticks: u32,
+}
+
+impl FanHealthMonitor {
+    pub fn new() -> Self {
+        Self {
+            health: FanHealth::Ok,
+            candidate: FanHealth::Ok,
+            candidate_ticks: 0,
+        }
+    }
+
+    pub fn health(&self) -> FanHealth {
+        self.health
+    }
+
+    /// Returns the new health when it changed this tick
+    pub fn update(&mut self, boost: u8, rpm: i64, expected_rpm: Option<u32>) -> Option<FanHealth> {
+        let observed = classify(boost, rpm, expected_rpm);
+        if observed == self.health {
+            self.candidate_ticks = 0;
+            return None;
+        }
+        if observed != self.candidate {
+            self.candidate = observed;
+            self.candidate_ticks = 0;
+        }
+        self.candidate_ticks += 1;
+        if self.candidate_ticks < SETTLE_TICKS {
+            return None;
+        }
+        self.health = observed;
+        self.candidate_ticks = 0;
+        Some(observed)
+    }
+}
+
+fn classify(boost: u8, rpm: i64, expected_rpm: Option<u32>) -> FanHealth {
+    if boost >= HIGH_BOOST && rpm <= 0 {
+        return FanHealth::Failed;
+    }
+    match expected_rpm {
+        Some(expected) if rpm * 2 < expected as i64 => FanHealth::Degraded,
+        _ => FanHealth::Ok,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::{
+        control::{ControlCommand, Reply},
+        controller::{
+            line_to_coords, set_fan_boost, BoostCurve, Controller, FanCurve, ALIEN_DEVICES,
+        },
+        emergency::ThermalEmergency,
+        rpm_control::get_calibration_from_string,
+        simulated::{drive, test_controller, with_simulated},
+        GraphType,
+    };
+
+    #[test]
+    fn classifies_from_boost_rpm_and_calibration() {
+        assert_eq!(classify(200, 0, None), FanHealth::Failed);
+        assert_eq!(classify(100, 0, None), FanHealth::Ok);
+        assert_eq!(classify(200, 900, Some(2000)), FanHealth::Degraded);
+        assert_eq!(classify(200, 1000, Some(2000)), FanHealth::Ok);
+        assert_eq!(classify(200, 900, None), FanHealth::Ok);
+    }
+
+    #[test]
+    fn a_classification_has_to_settle_before_it_sticks() {
+        let mut monitor = FanHealthMonitor::new();
+        for _ in 1..SETTLE_TICKS {
+            assert_eq!(monitor.update(200, 0, None), None);
+        }
+        // a good reading in between starts the count over
+        assert_eq!(monitor.update(200, 4000, None), None);
+        for _ in 1..SETTLE_TICKS {
+            assert_eq!(monitor.update(200, 0, None), None);
+        }
+        assert_eq!(monitor.update(200, 0, None), Some(FanHealth::Failed));
+        assert_eq!(monitor.health(), FanHealth::Failed);
+        assert_eq!(monitor.update(200, 0, None), None);
+    }
+
+    #[test]
+    fn a_failed_fan_sends_every_fan_to_full_boost_without_power_mode() {
+        with_simulated(|sim| {
+            sim.set_power_mode(0xab);
+            set_fan_boost(50, 200);
+            sim.set_stuck_rpm(50, Some(0));
+
+            let (failed, healed) = drive(&mut test_controller(), |handle| {
+                let status = || {
+                    let Ok(Reply::Status(status)) = handle.send(ControlCommand::Status) else {
+                        panic!("no status");
+                    };
+                    status
+                };
+                // the first tick already saw the fan stuck once
+                for _ in 1..SETTLE_TICKS {
+                    handle.send(ControlCommand::Next).unwrap();
+                }
+                let failed = (status(), sim.boost(50), sim.boost(51));
+                sim.set_stuck_rpm(50, None);
+                for _ in 0..SETTLE_TICKS {
+                    handle.send(ControlCommand::Next).unwrap();
+                }
+                (failed, (status(), sim.boost(50)))
+            });
+
+            let (status, cpu, gpu) = failed;
+            assert_eq!(status.fans[0].health, "failed");
+            assert_eq!((status.power_mode, cpu, gpu), (0, 255, 255));
+            let (status, cpu) = healed;
+            assert_eq!(status.fans[0].health, "ok");
+            // back on the curve, 40°C is 133 on it
+            assert_eq!(cpu, 133);
+        });
+    }
+
+    #[test]
+    fn boost_curves_with_a_calibration_table_catch_slow_fans() {
+        with_simulated(|sim| {
+            let graph = line_to_coords("(0 0), (60 200)").unwrap();
+            let (cpu_cal, gpu_cal) =
+                get_calibration_from_string("(0 0), (200 4000)\n(0 0), (200 4000)").unwrap();
+            let curve = |calibration| {
+                FanCurve::Boost(
+                    BoostCurve::new(graph.clone(), GraphType::Linear)
+                        .with_calibration(Some(calibration)),
+                )
+            };
+            let mut controller = Controller::new(
+                ALIEN_DEVICES,
+                [curve(cpu_cal), curve(gpu_cal)],
+                ThermalEmergency::new(vec![], Duration::ZERO),
+            );
+            // 40°C is 133 on the curve, where the table expects 2660 rpm
+            sim.set_stuck_rpm(50, Some(1000));
+
+            let status = drive(&mut controller, |handle| {
+                for _ in 0..SETTLE_TICKS {
+                    handle.send(ControlCommand::Next).unwrap();
+                }
+                let Ok(Reply::Status(status)) = handle.send(ControlCommand::Status) else {
+                    panic!("no status");
+                };
+                status
+            });
+
+            assert_eq!(status.fans[0].health, "degraded");
+            assert_eq!(status.fans[1].health, "ok");
+            // degraded is only reported, the curve keeps driving the fan
+            assert_eq!(status.fans[0].boost, 133);
+        });
+    }
+}
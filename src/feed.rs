@@ -0,0 +1,140 @@
+use std::sync::{
+    mpsc::{self, Receiver, SyncSender, TrySendError},
+    Mutex,
+};
+
+use serde::Serialize;
+
+use crate::{control::Status, events::Event};
+
+/// How many undelivered items a subscriber may fall behind by
+const BACKLOG: usize = 16;
+
+/// What the controller and event emitters publish to
+static FEED: Feed = Feed::new();
+
+/// Readbacks taken right after a tick
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+impl Sample {
+    pub fn new(status: Status) -> Self {
+        Self {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            status,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedItem {
+    Sample(Sample),
+    Event(Event),
+}
+
+struct Subscriber {
+    tx: SyncSender<FeedItem>,
+    samples: bool,
+}
+
+/// Fans items out to subscribers, the process-wide one is behind the free
+/// functions
+pub struct Feed {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl Feed {
+    pub const fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Events, and per-tick samples if `samples`, from now on. A subscriber
+    /// that falls [`BACKLOG`] items behind misses samples, and is dropped
+    /// once it can't take an event either. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self, samples: bool) -> Receiver<FeedItem> {
+        let (tx, rx) = mpsc::sync_channel(BACKLOG);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .push(Subscriber { tx, samples });
+        rx
+    }
+
+    pub fn wants_samples(&self) -> bool {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|sub| sub.samples)
+    }
+
+    /// Never blocks, whatever the subscribers do
+    pub fn publish(&self, item: FeedItem) {
+        let is_sample = matches!(item, FeedItem::Sample(_));
+        self.subscribers.lock().unwrap().retain(|sub| {
+            if is_sample && !sub.samples {
+                return true;
+            }
+            match sub.tx.try_send(item.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => is_sample,
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+pub fn subscribe(samples: bool) -> Receiver<FeedItem> {
+    FEED.subscribe(samples)
+}
+
+/// Lets the controller skip the readbacks when nobody is listening
+pub fn wants_samples() -> bool {
+    FEED.wants_samples()
+}
+
+pub fn publish_sample(sample: Sample) {
+    FEED.publish(FeedItem::Sample(sample));
+}
+
+pub fn publish_event(event: Event) {
+    FEED.publish(FeedItem::Event(event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Sample {
+        Sample::new(Status {
+            power_mode: 0,
+            profile: String::from("default"),
+            emergency: false,
+            fans: vec![],
+        })
+    }
+
+    #[test]
+    fn slow_subscribers_skip_samples_then_get_dropped() {
+        // a feed of its own, other tests publish to the process-wide one
+        let feed = Feed::new();
+        let rx = feed.subscribe(true);
+        for _ in 0..BACKLOG * 4 {
+            feed.publish(FeedItem::Sample(sample()));
+        }
+        // the backlog is full, an event can't be queued so the subscriber goes
+        feed.publish(FeedItem::Event(Event::ThermalEmergencyCleared));
+        assert!(!feed.wants_samples());
+
+        let items: Vec<FeedItem> = rx.iter().collect();
+        assert_eq!(items.len(), BACKLOG);
+        assert!(items.iter().all(|item| matches!(item, FeedItem::Sample(_))));
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, Write},
+    path::Path,
+    process,
+};
+
+pub const DEFAULT_LOCK_PATH: &str = "/run/awc.pid";
+
+const ACPI_CALL_FPATH: &str = "/proc/acpi/call";
+
+/// Held for as long as this process controls the fans. The lock goes away
+/// with the process, however it dies, so a stale pidfile never blocks a start.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Fails if another awc holds the lock at `path`
+    pub fn acquire(path: &Path) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        if file.try_lock().is_err() {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            return Err(format!(
+                "awc is already controlling the fans (pid {}, lock {})",
+                pid.trim(),
+                path.display()
+            ));
+        }
+        file.set_len(0).unwrap();
+        file.rewind().unwrap();
+        writeln!(file, "{}", process::id()).unwrap();
+        Ok(Self { _file: file })
+    }
+}
+
+/// Other processes that have `/proc/acpi/call` open, as `pid (name)`. Most
+/// tools only open it for a moment, so an empty list proves nothing.
+pub fn acpi_call_holders() -> Vec<String> {
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+    let me = process::id().to_string();
+    procs
+        .flatten()
+        .filter_map(|proc| {
+            let pid = proc.file_name().into_string().ok()?;
+            if pid == me || !pid.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let holds_it = fs::read_dir(proc.path().join("fd"))
+                .ok()?
+                .flatten()
+                .any(|fd| {
+                    fs::read_link(fd.path()).is_ok_and(|link| link == Path::new(ACPI_CALL_FPATH))
+                });
+            if !holds_it {
+                return None;
+            }
+            let name = fs::read_to_string(proc.path().join("comm")).unwrap_or_default();
+            Some(format!("{pid} ({})", name.trim()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{
+        control::ControlCommand,
+        controller::set_fan_boost,
+        events::Event,
+        feed::{self, FeedItem},
+        simulated::{drive, test_controller, with_simulated},
+    };
+
+    #[test]
+    fn a_second_instance_is_refused_until_the_first_goes_away() {
+        let path = env::temp_dir().join(format!("awc-{}.pid", process::id()));
+        let first = InstanceLock::acquire(&path).unwrap();
+
+        let second = InstanceLock::acquire(&path).unwrap_err();
+        assert!(second.contains(&process::id().to_string()));
+
+        drop(first);
+        assert!(InstanceLock::acquire(&path).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn boosts_changed_behind_our_back_are_reported_once_until_they_clear() {
+        with_simulated(|_| {
+            let events = feed::subscribe(false);
+            drive(&mut test_controller(), |handle| {
+                // answered after the first tick, then what another
+                // acpi_call tool would do between two ticks
+                handle.send(ControlCommand::Status).unwrap();
+                set_fan_boost(50, 7);
+                handle.send(ControlCommand::Next).unwrap();
+                // and keeps doing
+                set_fan_boost(50, 7);
+                handle.send(ControlCommand::Next).unwrap();
+                handle.send(ControlCommand::Next).unwrap();
+                set_fan_boost(50, 8);
+                handle.send(ControlCommand::Next).unwrap();
+            });
+
+            let conflicts: Vec<u8> = events
+                .try_iter()
+                .filter_map(|item| match item {
+                    FeedItem::Event(Event::BoostConflict {
+                        fan_id: 50, read, ..
+                    }) => Some(read),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(conflicts, [7, 8]);
+        });
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    env, fmt,
+    io::{self, IsTerminal, Write},
+    os::unix::net::UnixDatagram,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+/// Where journald takes native protocol datagrams
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const CYAN: &str = "\x1b[36m";
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Human as u8);
+static COLOR: AtomicBool = AtomicBool::new(false);
+static JOURNAL: OnceLock<Option<UnixDatagram>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// syslog priority, as journald wants it
+    fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug => 7,
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Timestamped lines, coloured on a terminal
+    Human,
+    /// One JSON object per line on stdout
+    Json,
+    /// journald's native protocol, with every field kept
+    Journald,
+}
+
+/// Sets up logging for the process. Without a `format`, logs go to the
+/// journal when systemd connected stdout to it, else they are for humans.
+pub fn init(format: Option<Format>, verbose: bool, quiet: bool) {
+    let format = format.unwrap_or(if env::var_os("JOURNAL_STREAM").is_some() {
+        Format::Journald
+    } else {
+        Format::Human
+    });
+    let level = match (verbose, quiet) {
+        (true, _) => Level::Debug,
+        (_, true) => Level::Warn,
+        _ => Level::Info,
+    };
+    LEVEL.store(level as u8, Ordering::SeqCst);
+    FORMAT.store(format as u8, Ordering::SeqCst);
+    // https://no-color.org
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    COLOR.store(
+        format == Format::Human && !no_color && io::stdout().is_terminal(),
+        Ordering::SeqCst,
+    );
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::SeqCst)
+}
+
+fn format() -> Format {
+    match FORMAT.load(Ordering::SeqCst) {
+        f if f == Format::Json as u8 => Format::Json,
+        f if f == Format::Journald as u8 => Format::Journald,
+        _ => Format::Human,
+    }
+}
+
+/// Whether output may use ANSI colours
+pub fn color() -> bool {
+    COLOR.load(Ordering::SeqCst)
+}
+
+/// A value printed in a colour, or plainly when colours are off
+pub struct Styled<T>(&'static str, T);
+
+impl<T: fmt::Display> fmt::Display for Styled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if color() {
+            write!(f, "{}{}{RESET}", self.0, self.1)
+        } else {
+            write!(f, "{}", self.1)
+        }
+    }
+}
+
+pub fn bold<T: fmt::Display>(value: T) -> Styled<T> {
+    Styled(BOLD, value)
+}
+
+pub fn green<T: fmt::Display>(value: T) -> Styled<T> {
+    Styled(GREEN, value)
+}
+
+pub fn yellow<T: fmt::Display>(value: T) -> Styled<T> {
+    Styled(YELLOW, value)
+}
+
+/// Writes one record in the configured format. Use the macros instead.
+pub fn write(level: Level, target: &str, fields: &[(&str, String)], message: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let message = message.to_string();
+    match format() {
+        Format::Human => {
+            let line = human(level, &message, color());
+            // stdout and stderr are separate streams, errors go where errors go
+            if level <= Level::Warn {
+                let _ = writeln!(io::stderr(), "{line}");
+            } else {
+                let _ = writeln!(io::stdout(), "{line}");
+            }
+        }
+        Format::Json => {
+            let _ = writeln!(io::stdout(), "{}", json(level, target, fields, &message));
+        }
+        Format::Journald => {
+            let sent = JOURNAL
+                .get_or_init(|| UnixDatagram::unbound().ok())
+                .as_ref()
+                .is_some_and(|socket| {
+                    let datagram = journal(level, target, fields, &message);
+                    socket.send_to(&datagram, JOURNAL_SOCKET).is_ok()
+                });
+            if !sent {
+                // journald reads `<priority>` prefixes on its stdout streams
+                let _ = writeln!(io::stderr(), "<{}>{message}", level.priority());
+            }
+        }
+    }
+}
+
+fn human(level: Level, message: &str, color: bool) -> String {
+    let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    let label = format!("{:5}", level.as_str().to_uppercase());
+    if !color {
+        return format!("{time} {label} {message}");
+    }
+    let style = match level {
+        Level::Error => RED,
+        Level::Warn => YELLOW,
+        Level::Info => GREEN,
+        Level::Debug => BLUE,
+    };
+    format!("{CYAN}{time}{RESET} {BOLD}{style}{label}{RESET} {message}")
+}
+
+fn json(level: Level, target: &str, fields: &[(&str, String)], message: &str) -> String {
+    let mut record = json!({
+        "timestamp": chrono::Local::now().to_rfc3339(),
+        "level": level.as_str(),
+        "target": target,
+        "message": message,
+    });
+    for (key, value) in fields {
+        record[*key] = Value::String(value.clone());
+    }
+    record.to_string()
+}
+
+/// `KEY=value` lines, values with a newline in them are length prefixed
+fn journal(level: Level, target: &str, fields: &[(&str, String)], message: &str) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    let mut field = |key: &str, value: &str| {
+        datagram.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    };
+    field("MESSAGE", message);
+    field("PRIORITY", &level.priority().to_string());
+    field("SYSLOG_IDENTIFIER", "awc");
+    field("CODE_MODULE", target);
+    for (key, value) in fields {
+        field(&key.to_uppercase(), value);
+    }
+    datagram
+}
+
+/// `log!(Level::Info, fan_id = 50, boost = 90; "Fan #{} ...", 50)`, the
+/// fields before the `;` are optional and kept by the JSON and journald formats
+macro_rules! log {
+    ($level:expr, $($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        $crate::log::write(
+            $level,
+            module_path!(),
+            &[$((stringify!($key), $value.to_string())),+],
+            format_args!($($arg)+),
+        )
+    };
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::write($level, module_path!(), &[], format_args!($($arg)+))
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { log!($crate::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => { log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { log!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_keep_the_fields() {
+        let fields = [("fan_id", String::from("50"))];
+        let line: Value =
+            serde_json::from_str(&json(Level::Warn, "awc::watchdog", &fields, "stuck")).unwrap();
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["target"], "awc::watchdog");
+        assert_eq!(line["message"], "stuck");
+        assert_eq!(line["fan_id"], "50");
+
+        let datagram = journal(Level::Error, "awc", &fields, "two\nlines");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\nPRIORITY=3\n");
+        assert!(datagram.starts_with(&expected));
+        assert!(datagram.ends_with(b"FAN_ID=50\n"));
+
+        assert!(!human(Level::Info, "plain", false).contains('\x1b'));
+        assert!(human(Level::Info, "colour", true).contains(GREEN));
+    }
+}
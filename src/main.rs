@@ -1,22 +1,46 @@
 #![allow(unused)]
 
+#[macro_use]
+mod log;
+
+mod client;
+mod config;
+mod control;
 mod controller;
+mod dbus;
+mod emergency;
+mod events;
+mod fan_health;
+mod feed;
+mod instance;
+mod policy;
+mod recorder;
+mod reload;
+mod rpm_control;
+mod sd_notify;
+mod shutdown;
+mod signals;
+mod simulated;
+mod socket;
+mod state;
+mod watchdog;
 
 use std::{
-    fs::{self, File, OpenOptions},
-    io::{self, stdin, Read, Write},
-    ops::Deref,
-    os::unix::net::UnixListener,
-    sync::{
-        atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering},
-        Arc,
-    },
+    fs,
+    io::{self, stdin},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
     thread,
     time::Duration,
 };
 
-use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use control::{ControlCommand, SharedHandle};
 use controller::*;
+use emergency::*;
+use serde::{Deserialize, Serialize};
+use watchdog::*;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -24,26 +48,187 @@ use controller::*;
 struct CmdArgs {
     #[command(subcommand)]
     commands: Commands,
+
+    /// Talk to an in-memory fan simulation instead of /proc/acpi/call
+    #[arg(long, global = true, hide = true)]
+    simulate: bool,
+
+    /// Control socket of the running `awc watch`, served by `watch` and used by the other commands
+    #[arg(long, global = true, default_value_t = String::from(socket::DEFAULT_SOCKET_PATH))]
+    socket: String,
+
+    /// Call ACPI directly even when `awc watch` is running, its next tick may undo the change
+    #[arg(long, global = true)]
+    direct: bool,
+
+    /// json5 config naming the devices, curves and interval [default: $AWC_CONFIG,
+    /// else $XDG_CONFIG_HOME/awc/awc.conf, else /etc/awc.conf]. Drop-ins in
+    /// /etc/awc.d/*.conf are merged over it
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// Override a config key, as KEY=VALUE, can be repeated. Wins over the
+    /// AWC_<KEY> env vars, which win over the config files
+    #[arg(long, global = true, value_name = "KEY=VALUE")]
+    set: Vec<config::Override>,
+
+    /// Log the unchanged boosts of every tick and other details too
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// How to write the log [default: journald under systemd, else human]
+    #[arg(long, global = true, value_enum)]
+    log_format: Option<log::Format>,
 }
 
-#[derive(Debug, ValueEnum, Clone, Copy)]
+/// How a curve is read between its points
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum GraphType {
+    /// Interpolate between the two surrounding points
     Linear,
+    /// Hold the value of the next point up
     Step,
 }
 
+/// What the second value of every graph point is
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum CurveTarget {
+    /// Raw fan boost, 0-255
+    Boost,
+    /// Fan rpm, reached with a per fan feedback loop
+    Rpm,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileArg {
+    name: String,
+    path: String,
+}
+
+impl FromStr for ProfileArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok(Self {
+                name: name.to_string(),
+                path: path.to_string(),
+            }),
+            _ => Err(format!("expected NAME=PATH, got {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    /// Seconds between ticks [default: the config's interval, else 30]
+    #[arg(short, long)]
+    interval: Option<u64>,
+
+    /// Two line graph file, takes the place of the config's curves [default: /etc/awc-graph without a config]
+    #[arg(short, long)]
+    path: Option<String>,
+
+    /// Overrides the config's per device graph_type [default: linear without a config]
+    #[arg(short, long, value_enum)]
+    graph: Option<GraphType>,
+
+    #[arg(short, long, value_enum, default_value_t = CurveTarget::Boost)]
+    target: CurveTarget,
+
+    /// Boost to rpm table per fan, tells slow fans apart and is the feedforward with `--target rpm`
+    #[arg(short, long)]
+    calibration: Option<String>,
+
+    /// How far off the target rpm a fan may be before the boost is adjusted
+    #[arg(long, default_value_t = 100)]
+    rpm_tolerance: u32,
+
+    /// Seconds a fan may sit at the same rpm before the watchdog fires [default: interval * 3]
+    #[arg(long)]
+    watchdog_timeout: Option<u64>,
+
+    /// Rpm changes up to this much still count as "the same rpm" for the watchdog
+    #[arg(long, default_value_t = 0)]
+    watchdog_tolerance: u32,
+
+    #[arg(long, value_enum, default_value_t = RecoveryAction::PulseZero)]
+    watchdog_action: RecoveryAction,
+
+    /// Shell command run on every event, details are passed in `AWC_*` env vars
+    #[arg(long)]
+    event_hook: Option<String>,
+
+    /// Show a desktop notification for critical events
+    #[arg(long)]
+    notify: bool,
+
+    /// Sensor temperature that forces every fan to full boost, as
+    /// SENSOR=CRITICAL[:RELEASE], can be repeated
+    #[arg(long)]
+    critical: Vec<CriticalThreshold>,
+
+    /// Seconds every sensor has to stay below its release temperature to end an emergency
+    #[arg(long, default_value_t = 30)]
+    release_hold: u64,
+
+    /// Extra fan curves that can be switched to at runtime, as NAME=PATH,
+    /// can be repeated. The curves from `--path` are the `default` profile
+    #[arg(long)]
+    profile: Vec<ProfileArg>,
+
+    /// Don't read commands from stdin, control comes from signals and the socket
+    #[arg(long)]
+    no_interactive: bool,
+
+    /// Bus to publish the org.awc.FanControl D-Bus service on
+    #[arg(long, value_enum, default_value_t = dbus::Bus::System)]
+    bus: dbus::Bus,
+
+    /// File the profile, manual overrides and original power mode are kept in
+    /// across restarts
+    #[arg(long, default_value_t = String::from(state::DEFAULT_STATE_PATH))]
+    state: String,
+
+    /// Pidfile locked while awc controls the fans, a second instance refuses to start
+    #[arg(long, default_value_t = String::from(instance::DEFAULT_LOCK_PATH))]
+    lock: String,
+
+    /// File a row of temperatures, rpms, boosts and overrides is appended to
+    /// every tick
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// [default: jsonl for .jsonl and .json files, else csv]
+    #[arg(long, value_enum, requires = "record")]
+    record_format: Option<recorder::RecordFormat>,
+
+    /// Start a new recording once the current one reaches this many MiB,
+    /// the old one is gzipped
+    #[arg(
+        long,
+        requires = "record",
+        value_parser = clap::value_parser!(u64).range(1..=u64::MAX / (1024 * 1024))
+    )]
+    record_max_size: Option<u64>,
+
+    /// Start a new recording once the current one is this many seconds old,
+    /// the old one is gzipped
+    #[arg(long, requires = "record")]
+    record_max_age: Option<u64>,
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
-    Watch {
-        #[arg(default_value_t = 30, short, long)]
-        interval: u64,
-
-        #[arg(short, long, default_value_t = String::from("/etc/awc-graph"))]
-        path: String,
+    Watch(WatchArgs),
 
-        #[arg(short, long, value_enum, default_value_t = GraphType::Linear)]
-        graph: GraphType,
-    },
+    /// `watch --no-interactive`, for running under a service manager
+    Daemon(WatchArgs),
 
     Info,
 
@@ -54,6 +239,67 @@ enum Commands {
     Fans {
         #[arg(short, long)]
         boost: Option<u8>,
+
+        /// With a running daemon, only pin the boost for this many seconds
+        #[arg(long, requires = "boost")]
+        seconds: Option<u64>,
+
+        /// With a running daemon, hand the fans back to their curves
+        #[arg(long, conflicts_with = "boost")]
+        auto: bool,
+    },
+
+    /// Inspect the config files
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// List the config files in the order they are merged
+    Show {
+        /// Print the merged config instead, with the file every key comes from
+        #[arg(long)]
+        effective: bool,
+    },
+
+    /// Validate the config files against the schema and this machine's fans and sensors
+    Check,
+
+    /// Write a starter config for the fans and sensors of this machine
+    Init {
+        /// Where to write it [default: $XDG_CONFIG_HOME/awc/awc.conf]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Replace the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Turn a two line graph file or an old cpu_graph/gpu_graph config into a config
+    Migrate {
+        /// The graph file `watch --path` was given, or the old config
+        #[arg(long)]
+        from: PathBuf,
+
+        /// The `--interval` `watch` was started with [default: 30]
+        #[arg(short, long)]
+        interval: Option<u64>,
+
+        /// The `--graph` `watch` was started with [default: the old config's graph_type, else linear]
+        #[arg(short, long, value_enum)]
+        graph: Option<GraphType>,
+
+        /// Where to write the config [default: print it]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Replace the file if it exists
+        #[arg(long)]
+        force: bool,
     },
 }
 
@@ -63,104 +309,545 @@ fn main() {
 }
 
 fn handle_args(args: CmdArgs) {
+    log::init(args.log_format, args.verbose, args.quiet);
+    if args.simulate {
+        simulated::install(Arc::new(simulated::SimulatedBackend::new()));
+    }
+
+    let files = config::lookup(args.config.as_deref().map(Path::new)).unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(1);
+    });
+    let config_source = config::ConfigSource {
+        files,
+        sets: args.set,
+    };
+    // looking at a broken config must not need a working one
+    if let Commands::Config { command } = args.commands {
+        handle_config_command(command, &config_source);
+        return;
+    }
+
+    if config_source.is_empty() && config_source.has_overrides() {
+        warn!("No config file, ignoring the --set and AWC_<KEY> overrides");
+    }
+    // the config, when there is one, names the devices for every command
+    let config = (!config_source.is_empty()).then(|| {
+        config_source.load().unwrap_or_else(|e| {
+            error!("{e}");
+            std::process::exit(1);
+        })
+    });
+    let devices = config.as_ref().map_or(ALIEN_DEVICES, |config| {
+        let (cpu, gpu) = config.devices();
+        devices_from_ids(cpu, gpu)
+    });
+    let config = config.map(|config| (config_source, config));
+
     match args.commands {
-        Commands::Watch {
+        Commands::Watch(watch_args) => {
+            let interactive = !watch_args.no_interactive;
+            watch(watch_args, devices, config, args.socket, interactive);
+        }
+        Commands::Daemon(watch_args) => {
+            watch(watch_args, devices, config, args.socket, false);
+        }
+        Commands::Config { .. } => unreachable!(),
+        Commands::Info | Commands::Temps | Commands::Mode | Commands::Fans { .. } => {
+            // with a daemon running, go through it instead of racing its next tick
+            let daemon = if args.direct {
+                None
+            } else {
+                match client::Client::connect(&args.socket) {
+                    Ok(daemon) => Some(daemon),
+                    // no daemon to race
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                        ) =>
+                    {
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Can't reach the awc daemon on {}: {e}\n\
+                             Pass --direct to talk to the firmware anyway",
+                            args.socket
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            };
+            match daemon {
+                Some(mut daemon) => {
+                    if let Err(e) = handle_client_command(args.commands, &mut daemon) {
+                        eprintln!("awc daemon: {e}");
+                        std::process::exit(1);
+                    }
+                }
+                None => handle_direct_command(args.commands, &devices),
+            }
+        }
+    };
+}
+
+fn handle_config_command(command: ConfigCommand, source: &config::ConfigSource) {
+    match command {
+        ConfigCommand::Show { effective: false } => {
+            if source.is_empty() {
+                println!("No config files, using the built in devices and /etc/awc-graph");
+                if source.has_overrides() {
+                    println!("Ignoring these overrides, they need a config file:");
+                }
+            }
+            for file in &source.files {
+                println!("{}", file.display());
+            }
+            for (var, _, value) in config::env_overrides() {
+                println!("{var}={value}");
+            }
+            for set in &source.sets {
+                println!("--set {}={}", set.key, set.value);
+            }
+        }
+        ConfigCommand::Show { effective: true } => {
+            let effective = source.effective().unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+            println!("{effective}");
+            if let Err(e) = effective.config() {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        ConfigCommand::Check => {
+            if source.is_empty() {
+                println!("No config files to check");
+                return;
+            }
+            let probes = controller::probe()
+                .map_err(|e| warn!("{e}, not checking the fan and sensor ids"))
+                .ok();
+            let problems = config::check(&source.files, &source.sets, probes.as_ref());
+            for problem in &problems {
+                eprintln!("{problem}");
+            }
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+            println!("Config OK");
+        }
+        ConfigCommand::Init { output, force } => {
+            let Some(output) = output.or_else(config::user_path) else {
+                eprintln!("No user config dir, pass --output");
+                std::process::exit(1);
+            };
+            if output.exists() && !force {
+                eprintln!("{} exists, pass --force to replace it", output.display());
+                std::process::exit(1);
+            }
+            let probes = controller::probe().unwrap_or_else(|e| {
+                eprintln!("Can't probe the fans and sensors, {e}");
+                std::process::exit(1);
+            });
+            let written = config::starter(&probes).and_then(|s| write_config(&output, &s));
+            if let Err(e) = written {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            println!("Wrote {}", output.display());
+        }
+        ConfigCommand::Migrate {
+            from,
             interval,
-            path,
             graph,
+            output,
+            force,
         } => {
-            let signal = Arc::new(AtomicIsize::new(0));
-            let sig_clone = signal.clone();
-            let p = path.clone();
-            let mut buf = String::with_capacity(1024);
-            {
-                OpenOptions::new()
-                    .read(true)
-                    .open(path)
-                    .unwrap()
-                    .read_to_string(&mut buf)
-                    .unwrap();
-            }
-
-            let (cpu_graph, gpu_graph) = get_coords_from_string(&buf);
-
-            let alien_dev_infos = get_alien_dev_graph_info(cpu_graph.clone(), gpu_graph.clone());
-            print!("Update Interval: {interval} seconds and using fan curves from {p}\n");
-
-            let mut t = Some(thread::spawn(move || {
-                let mut controller = Controller::new(alien_dev_infos);
-                controller.watch(interval, graph, &sig_clone);
-            }));
-
-            loop {
-                buf.clear();
-                let size = stdin().read_line(&mut buf).unwrap();
-                if size != 0 {
-                    let cmd = buf.trim();
-                    match cmd {
-                        "q" => {
-                            print!("Exiting...\n");
-                            signal.store(-1, Ordering::SeqCst);
-                            break;
-                        }
-                        "m" => {
-                            print!("Changing Power mode\n");
-                            signal.store(1, Ordering::SeqCst);
-                        }
-                        "i" | "s" => {
-                            print!("Showing Info...\n");
-                            signal.store(2, Ordering::SeqCst);
-                        }
-                        "r" => {
-                            print!("Reloading...\n");
-                            signal.store(3, Ordering::SeqCst);
-                        }
-                        "n" => {
-                            signal.store(4, Ordering::SeqCst);
-                        }
-                        "p" => {
-                            if let Some(t) = t.take() {
-                                signal.store(-1, Ordering::SeqCst);
-                                t.join();
-                                print!("Paused Watch\n");
-                            } else {
-                                signal.store(0, Ordering::SeqCst);
-                                let sig_clone = signal.clone();
-                                let alien_dev_infos =
-                                    get_alien_dev_graph_info(cpu_graph.clone(), gpu_graph.clone());
-                                t = Some(thread::spawn(move || {
-                                    let mut controller = Controller::new(alien_dev_infos);
-                                    controller.watch(interval, graph, &sig_clone);
-                                }));
-                                print!("Resumed Watch\n");
-                            }
-                        }
-                        _ => {
-                            eprint!("Unknown command: {cmd}\n");
-                        }
+            if let Some(output) = output.as_ref().filter(|output| output.exists() && !force) {
+                eprintln!("{} exists, pass --force to replace it", output.display());
+                std::process::exit(1);
+            }
+            let migrated = fs::read_to_string(&from)
+                .map_err(|e| e.to_string())
+                .and_then(|s| {
+                    config::migrate(&s, interval, graph, controller::device_ids(&ALIEN_DEVICES))
+                })
+                .map(|config| {
+                    format!(
+                        "// Migrated from {} by `awc config migrate`\n{}",
+                        from.display(),
+                        config.to_json5()
+                    )
+                });
+            let migrated = match migrated {
+                Ok(migrated) => migrated,
+                Err(e) => {
+                    eprintln!("{}: {e}", from.display());
+                    std::process::exit(1);
+                }
+            };
+            match output {
+                Some(output) => {
+                    if let Err(e) = write_config(&output, &migrated) {
+                        eprintln!("{e}");
+                        std::process::exit(1);
                     }
+                    println!("Wrote {}", output.display());
                 }
+                None => print!("{migrated}"),
             }
-            print!("Joining thread...\n");
-            if let Some(t) = t {
-                t.join();
+        }
+    }
+}
+
+/// The administrator's limits, a broken policy file is fatal rather than ignored
+fn load_policy() -> Option<policy::Policy> {
+    policy::Policy::load(Path::new(policy::DEFAULT_POLICY_PATH)).unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(1);
+    })
+}
+
+fn write_config(path: &Path, s: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    fs::write(path, s).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn watch(
+    args: WatchArgs,
+    devices: Devices,
+    config: Option<(config::ConfigSource, config::AwcConfig)>,
+    socket: String,
+    interactive: bool,
+) {
+    let WatchArgs {
+        interval,
+        path,
+        graph,
+        target,
+        calibration,
+        rpm_tolerance,
+        watchdog_timeout,
+        watchdog_tolerance,
+        watchdog_action,
+        event_hook,
+        notify,
+        critical,
+        release_hold,
+        profile,
+        no_interactive: _,
+        bus,
+        state,
+        record,
+        record_format,
+        record_max_size,
+        record_max_age,
+        lock,
+    } = args;
+    // held until watch returns
+    let _lock = match instance::InstanceLock::acquire(Path::new(&lock)) {
+        Ok(lock) => lock,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(hook) = event_hook {
+        events::set_hook(hook);
+    }
+    events::set_notify(notify);
+    // after a crash the power mode is still whatever awc set, the state file
+    // knows what it was before
+    let original_power_mode = state::load(Path::new(&state))
+        .and_then(|saved| saved.original_power_mode)
+        .unwrap_or(controller::get_power_mode() as u8);
+    shutdown::install_exit_handlers(devices, original_power_mode);
+
+    let interval = interval
+        .or(config.as_ref().map(|(_, config)| config.interval))
+        .unwrap_or(30);
+    if let Err(e) = config::check_interval(interval) {
+        error!("{e}");
+        std::process::exit(1);
+    }
+    let curves = match (path, &config) {
+        (Some(path), _) => reload::CurveFile::Graph(path.into()),
+        (None, Some((config_source, _))) => reload::CurveFile::Config(config_source.clone()),
+        (None, None) => reload::CurveFile::Graph(PathBuf::from("/etc/awc-graph")),
+    };
+    if config
+        .as_ref()
+        .is_some_and(|(_, config)| config.disable_power_mode_on_startup)
+        && controller::get_power_mode() != 0
+    {
+        info!("Turning power mode off, as the config asks");
+        controller::set_power_mode(0);
+    }
+
+    let p = curves.to_string();
+    let source = reload::CurveSource::new(
+        curves,
+        profile
+            .into_iter()
+            .map(|profile| (profile.name, profile.path.into()))
+            .collect(),
+        calibration.map(Into::into),
+        target,
+        graph,
+        rpm_tolerance,
+    );
+    if let Err(e) = source.load() {
+        error!("{e}");
+        std::process::exit(1);
+    }
+    let policy = load_policy();
+    if policy.is_some() {
+        info!(
+            "Holding the fans to the limits in {}",
+            policy::DEFAULT_POLICY_PATH
+        );
+    }
+
+    let watchdog = Watchdog::new(
+        Duration::from_secs(watchdog_timeout.unwrap_or(interval.saturating_mul(3))),
+        watchdog_tolerance,
+        watchdog_action,
+    );
+    let start_controller = || {
+        let (handle, commands) = control::channel();
+        // a resume picks up edits made while paused, unless they are broken
+        let profiles = source.load().unwrap_or_else(|e| {
+            warn!("Keeping the last good curves: {e}");
+            source.last_good().unwrap()
+        });
+        let default_curves = profiles[DEFAULT_PROFILE].clone();
+        let emergency = ThermalEmergency::new(critical.clone(), Duration::from_secs(release_hold));
+        let source = source.clone();
+        let policy = policy.clone();
+        let state_path = PathBuf::from(&state);
+        let recorder = record.as_ref().map(|path| {
+            recorder::Recorder::new(
+                path.clone(),
+                record_format.unwrap_or_else(|| recorder::RecordFormat::for_path(path)),
+                record_max_size.map(|mib| mib * 1024 * 1024),
+                record_max_age.map(Duration::from_secs),
+            )
+        });
+        let t = thread::spawn(move || {
+            let mut controller = Controller::new(devices, default_curves, emergency);
+            for (name, curves) in profiles {
+                controller.add_profile(name, curves);
+            }
+            controller.set_curve_source(source);
+            controller.set_original_power_mode(original_power_mode);
+            if let Some(policy) = policy {
+                controller.set_policy(policy);
+            }
+            controller.restore_state(state_path);
+            if let Some(recorder) = recorder {
+                controller.set_recorder(recorder);
+            }
+            if let Some(notifier) = sd_notify::Notifier::from_env() {
+                controller.set_notifier(notifier);
+            }
+            controller.watch(interval, &watchdog, &commands);
+        });
+        (handle, t)
+    };
+    info!("Update interval: {interval} seconds and using fan curves from {p}");
+    if let Some(path) = &record {
+        info!("Recording every tick to {}", path.display());
+    }
+
+    // the controller's first tick sends READY=1, by then units ordered after
+    // awc must find the socket and the bus name
+    let shared = SharedHandle::default();
+    match socket::serve(&socket, shared.clone()) {
+        Ok(()) => info!("Listening for control requests on {socket}"),
+        Err(e) => warn!("Control socket {socket} unavailable: {e}"),
+    }
+    // dropping the service would take it off the bus
+    let _dbus = match dbus::serve(bus, shared.clone()) {
+        Ok(Some(service)) => {
+            info!("Serving {} on the {bus:?} bus", dbus::BUS_NAME);
+            Some(service)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("D-Bus service unavailable: {e}");
+            None
+        }
+    };
+    let mut running = Some(start_controller());
+    shared.set(running.as_ref().map(|(handle, _)| handle.clone()));
+    if let Err(e) = signals::spawn_signal_thread(shared.clone()) {
+        warn!("Signal handling unavailable: {e}");
+    }
+    if let Err(e) = reload::watch_files(source.paths(), shared.clone()) {
+        warn!("Not watching the curve files for changes: {e}");
+    }
+
+    let mut buf = String::with_capacity(1024);
+    let mut quit = false;
+    let mut stdin_open = interactive;
+    while stdin_open {
+        buf.clear();
+        let size = stdin().read_line(&mut buf).unwrap();
+        if size == 0 {
+            info!("stdin closed, control continues through signals and {socket}");
+            stdin_open = false;
+            continue;
+        }
+        let cmd = buf.trim();
+        let words: Vec<&str> = cmd.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["q"] => {
+                info!("Exiting...");
+                quit = true;
+                break;
+            }
+            ["m"] => {
+                info!("Changing Power mode");
+                ControlCommand::TogglePowerMode
+            }
+            ["i"] | ["s"] => {
+                info!("Showing Info...");
+                ControlCommand::ShowInfo
+            }
+            ["r"] => {
+                info!("Reloading...");
+                ControlCommand::Reload
+            }
+            ["n"] => ControlCommand::Next,
+            ["profile", name] => ControlCommand::SetProfile(name.to_string()),
+            ["boost", fan_id, boost] => match (fan_id.parse(), boost.parse()) {
+                (Ok(fan_id), Ok(boost)) => ControlCommand::SetBoost {
+                    fan_id,
+                    boost,
+                    duration: None,
+                },
+                _ => {
+                    error!("Usage: boost <fan id> <0-255>");
+                    continue;
+                }
+            },
+            ["auto"] => ControlCommand::ClearBoost { fan_id: None },
+            ["auto", fan_id] => match fan_id.parse() {
+                Ok(fan_id) => ControlCommand::ClearBoost {
+                    fan_id: Some(fan_id),
+                },
+                Err(_) => {
+                    error!("Usage: auto [fan id]");
+                    continue;
+                }
+            },
+            ["p"] => {
+                if let Some((handle, t)) = running.take() {
+                    shared.set(None);
+                    if let Err(e) = handle.send(ControlCommand::Quit) {
+                        error!("{e}");
+                    }
+                    if t.join().is_err() {
+                        error!("The watch thread panicked");
+                        std::process::exit(1);
+                    }
+                    info!("Paused Watch");
+                } else {
+                    running = Some(start_controller());
+                    shared.set(running.as_ref().map(|(handle, _)| handle.clone()));
+                    info!("Resumed Watch");
+                }
+                continue;
+            }
+            _ => {
+                error!("Unknown command: {cmd}");
+                continue;
+            }
+        };
+        match shared.send(command) {
+            Ok(reply) => info!("{reply}"),
+            Err(e) => error!("{e}"),
+        }
+    }
+    if !quit {
+        // headless, signals and the socket are the only way in from here.
+        // SIGTERM exits the process from the signal thread
+        match running {
+            Some((_, t)) => {
+                if t.join().is_err() {
+                    error!("The watch thread panicked");
+                    std::process::exit(1);
+                }
             }
+            None => loop {
+                thread::park();
+            },
         }
+        return;
+    }
+    if let Some((handle, t)) = running {
+        shared.set(None);
+        if let Err(e) = handle.send(ControlCommand::Quit) {
+            error!("{e}");
+        }
+        debug!("Joining thread...");
+        if t.join().is_err() {
+            error!("The watch thread panicked");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn handle_client_command(commands: Commands, daemon: &mut client::Client) -> Result<(), String> {
+    match commands {
+        Commands::Info => client::show_all_info(daemon),
+        Commands::Temps => client::show_temps(daemon),
+        Commands::Mode => client::toggle_power_mode(daemon),
+        Commands::Fans { auto: true, .. } => client::clear_fan_boosts(daemon),
+        Commands::Fans {
+            boost: Some(boost),
+            seconds,
+            ..
+        } => client::set_both_fan_boosts(daemon, boost, seconds),
+        Commands::Fans { .. } => client::show_fan_boosts(daemon),
+        Commands::Watch(_) | Commands::Daemon(_) | Commands::Config { .. } => unreachable!(),
+    }
+}
+
+fn handle_direct_command(commands: Commands, devices: &Devices) {
+    match commands {
         Commands::Info => {
-            show_all_info();
+            show_all_info(devices);
         }
         Commands::Temps => {
-            show_temps();
+            show_temps(devices);
         }
         Commands::Mode => {
+            let denied = load_policy().is_some_and(|policy| !policy.allow_power_mode);
+            if denied && get_power_mode() == 0 {
+                eprintln!("{POWER_MODE_DENIED}");
+                std::process::exit(1);
+            }
             toggle_power_mode();
         }
-        Commands::Fans { boost } => {
+        Commands::Fans { auto: true, .. } => {
+            eprintln!("No running awc watch to hand the fans back to");
+        }
+        Commands::Fans { boost, .. } => {
             if let Some(boost) = boost {
-                set_both_fan_boosts(boost);
+                match load_policy() {
+                    Some(policy) => set_both_fan_boosts_within(devices, boost, &policy),
+                    None => set_both_fan_boosts(devices, boost),
+                }
             } else {
-                show_fan_boosts();
+                show_fan_boosts(devices);
             }
         }
+        Commands::Watch(_) | Commands::Daemon(_) | Commands::Config { .. } => unreachable!(),
     };
 }
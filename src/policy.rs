@@ -0,0 +1,220 @@
+use std::{fmt, fs, io, path::Path};
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    config::check_graph,
+    controller::{BoostCurve, CoOrdinates},
+    GraphType,
+};
+
+/// Limits set by the administrator, which user configs and curves can't override
+pub const DEFAULT_POLICY_PATH: &str = "/etc/awc.d/policy";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Boost every fan gets at least, whatever its curve or pin says
+    #[serde(default)]
+    pub floor: Floor,
+    pub quiet_hours: Option<QuietHours>,
+    /// Whether power mode may be turned on
+    #[serde(default = "allowed")]
+    pub allow_power_mode: bool,
+}
+
+fn allowed() -> bool {
+    true
+}
+
+/// Linear temperature to boost curves per fan, nothing below their first point
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Floor {
+    #[serde(default)]
+    pub cpu: Vec<CoOrdinates>,
+    #[serde(default)]
+    pub gpu: Vec<CoOrdinates>,
+}
+
+/// From `start` to `end` local time, which may wrap past midnight, no fan
+/// goes over `max_boost`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHours {
+    #[serde(deserialize_with = "clock")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "clock")]
+    pub end: NaiveTime,
+    pub max_boost: u8,
+}
+
+/// `HH:MM`
+fn clock<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M")
+        .map_err(|e| serde::de::Error::custom(format!("{s} is not HH:MM: {e}")))
+}
+
+/// The policy limit that changed a boost
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Limit {
+    Floor,
+    QuietHours,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Floor => write!(f, "safety floor"),
+            Limit::QuietHours => write!(f, "quiet hours"),
+        }
+    }
+}
+
+impl Policy {
+    /// `None` when there is no policy file
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let s = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        let policy: Self = json5::from_str(&s).map_err(|e| format!("{}: {e}", path.display()))?;
+        let problems = policy.check();
+        if !problems.is_empty() {
+            return Err(format!("{}: {}", path.display(), problems.join(", ")));
+        }
+        Ok(Some(policy))
+    }
+
+    /// A floor is a curve like any other, but may be left out
+    fn check(&self) -> Vec<String> {
+        [
+            ("floor.cpu", &self.floor.cpu),
+            ("floor.gpu", &self.floor.gpu),
+        ]
+        .into_iter()
+        .filter(|(_, floor)| !floor.is_empty())
+        .flat_map(|(name, floor)| check_graph(name, floor))
+        .collect()
+    }
+
+    /// Keeps a curve's or pin's `boost` for fan `device` (0 for the CPU, 1
+    /// for the GPU) within the limits. The floor wins over the quiet hours,
+    /// a quiet fan is no excuse for an overheating machine.
+    pub fn clamp(&self, device: usize, temp: u8, boost: u8, now: NaiveTime) -> (u8, Option<Limit>) {
+        let mut clamped = (boost, None);
+        if let Some(quiet) = &self.quiet_hours {
+            if quiet.contains(now) && boost > quiet.max_boost {
+                clamped = (quiet.max_boost, Some(Limit::QuietHours));
+            }
+        }
+        let floor = match device {
+            0 => &self.floor.cpu,
+            _ => &self.floor.gpu,
+        };
+        let min = match floor.first() {
+            Some(first) if temp >= first.temp => {
+                BoostCurve::new(floor.clone(), GraphType::Linear).boost(temp)
+            }
+            _ => 0,
+        };
+        if clamped.0 < min {
+            clamped = (min, Some(Limit::Floor));
+        }
+        clamped
+    }
+}
+
+impl QuietHours {
+    fn contains(&self, now: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= now && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        controller::{set_both_fan_boosts_within, ALIEN_DEVICES},
+        simulated::with_simulated,
+    };
+
+    fn at(clock: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(clock, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn floor_beats_quiet_hours_which_beat_the_curve() {
+        let policy: Policy = json5::from_str(
+            r#"{
+                floor: { cpu: [{ temp: 70, fan_boost: 100 }, { temp: 80, fan_boost: 200 }] },
+                quiet_hours: { start: "22:00", end: "07:00", max_boost: 50 },
+                allow_power_mode: false,
+            }"#,
+        )
+        .unwrap();
+        assert!(!policy.allow_power_mode);
+
+        // during the day only the floor applies, and only from its first point
+        assert_eq!(policy.clamp(0, 40, 120, at("12:00")), (120, None));
+        assert_eq!(policy.clamp(0, 40, 0, at("12:00")), (0, None));
+        assert_eq!(
+            policy.clamp(0, 75, 20, at("12:00")),
+            (150, Some(Limit::Floor))
+        );
+        assert_eq!(policy.clamp(1, 75, 20, at("12:00")), (20, None));
+
+        // quiet hours wrap past midnight
+        assert_eq!(
+            policy.clamp(1, 40, 120, at("23:30")),
+            (50, Some(Limit::QuietHours))
+        );
+        assert_eq!(
+            policy.clamp(1, 40, 120, at("06:59")),
+            (50, Some(Limit::QuietHours))
+        );
+        assert_eq!(policy.clamp(1, 40, 120, at("07:00")), (120, None));
+        assert_eq!(
+            policy.clamp(0, 80, 120, at("23:30")),
+            (200, Some(Limit::Floor))
+        );
+    }
+
+    #[test]
+    fn falling_floors_are_refused() {
+        let path = std::env::temp_dir().join(format!("awc-{}-policy", std::process::id()));
+        fs::write(
+            &path,
+            "{ floor: { gpu: [{ temp: 60, fan_boost: 200 }, { temp: 80, fan_boost: 100 }] } }",
+        )
+        .unwrap();
+        let error = Policy::load(&path).unwrap_err();
+        assert!(
+            error.contains("floor.gpu boosts may not go down"),
+            "{error}"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn direct_boosts_are_held_to_the_policy_too() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 90);
+            sim.set_temp(6, 40);
+            let policy: Policy =
+                json5::from_str("{ floor: { cpu: [{ temp: 80, fan_boost: 220 }] } }").unwrap();
+
+            set_both_fan_boosts_within(&ALIEN_DEVICES, 30, &policy);
+
+            assert_eq!((sim.boost(50), sim.boost(51)), (220, 30));
+        });
+    }
+}
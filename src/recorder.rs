@@ -0,0 +1,391 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use flate2::{write::GzEncoder, Compression};
+use serde_json::{json, Value};
+
+use crate::control::{FanStatus, Status};
+
+/// How recorded rows are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RecordFormat {
+    /// A header line, then one comma separated row per tick
+    Csv,
+    /// One JSON object per tick
+    Jsonl,
+}
+
+impl RecordFormat {
+    /// `jsonl` for `.jsonl` and `.json` files, else `csv`
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "json") => RecordFormat::Jsonl,
+            _ => RecordFormat::Csv,
+        }
+    }
+}
+
+/// Appends a row per tick to `path`. Once the current segment is over
+/// `max_size` bytes or older than `max_age`, it is renamed after the time it
+/// was started and queued to be gzipped in the background, and a new one is
+/// begun.
+pub struct Recorder {
+    path: PathBuf,
+    format: RecordFormat,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    file: Option<BufWriter<File>>,
+    /// Bytes in the current segment
+    size: u64,
+    /// When this recorder started the current segment, or took it over
+    opened: Instant,
+    started: chrono::DateTime<chrono::Local>,
+    /// Gzips the rotated segments one after the other, started on the first
+    compressor: Option<(Sender<PathBuf>, JoinHandle<()>)>,
+}
+
+impl Recorder {
+    pub fn new(
+        path: PathBuf,
+        format: RecordFormat,
+        max_size: Option<u64>,
+        max_age: Option<Duration>,
+    ) -> Self {
+        Self {
+            path,
+            format,
+            max_size,
+            max_age,
+            file: None,
+            size: 0,
+            opened: Instant::now(),
+            started: chrono::Local::now(),
+            compressor: None,
+        }
+    }
+
+    /// Appends `status` as one row. Errors are logged and the row dropped,
+    /// recording never gets in the way of controlling the fans.
+    pub fn record(&mut self, status: &Status) {
+        if let Err(e) = self.try_record(status) {
+            error!("Recording to {}: {e}", self.path.display());
+            // start over with a fresh handle next tick
+            self.file = None;
+        }
+    }
+
+    fn try_record(&mut self, status: &Status) -> io::Result<()> {
+        if self.file.is_some() && self.is_full() {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            self.open()?;
+        }
+        let mut line = match self.format {
+            RecordFormat::Csv => csv_row(status),
+            RecordFormat::Jsonl => json_row(status).to_string(),
+        };
+        if self.size == 0 && self.format == RecordFormat::Csv {
+            line = format!("{}\n{line}", csv_header(status));
+        }
+        line.push('\n');
+        let file = self.file.as_mut().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn is_full(&self) -> bool {
+        self.max_size.is_some_and(|max| self.size >= max)
+            || self.max_age.is_some_and(|max| self.opened.elapsed() >= max)
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        if self.size == 0 {
+            self.opened = Instant::now();
+            self.started = chrono::Local::now();
+        }
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    /// `telemetry.csv` becomes `telemetry-20261019T031248.csv.gz`
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let segment = self.segment_path();
+        fs::rename(&self.path, &segment)?;
+        info!("Started a new recording, compressing {}", segment.display());
+        let (segments, _) = self.compressor.get_or_insert_with(|| {
+            let (segments, queue) = mpsc::channel::<PathBuf>();
+            let compressing = thread::spawn(move || {
+                for segment in queue {
+                    if let Err(e) = compress(&segment) {
+                        error!("Compressing {}: {e}", segment.display());
+                    }
+                }
+            });
+            (segments, compressing)
+        });
+        // only fails if the compressor panicked, the segment stays uncompressed
+        let _ = segments.send(segment);
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Named after the second the segment was started, with a counter when
+    /// an earlier segment, or its `.gz`, already has that name
+    fn segment_path(&self) -> PathBuf {
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let started = self.started.format("%Y%m%dT%H%M%S");
+        (0..)
+            .map(|n| {
+                let mut name = match n {
+                    0 => format!("{stem}-{started}"),
+                    n => format!("{stem}-{started}-{n}"),
+                };
+                if let Some(ext) = self.path.extension() {
+                    name.push('.');
+                    name.push_str(&ext.to_string_lossy());
+                }
+                self.path.with_file_name(name)
+            })
+            .find(|segment| !segment.exists() && !gz_path(segment).exists())
+            .unwrap()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some((segments, compressing)) = self.compressor.take() {
+            // the queue ends once the sender is gone
+            drop(segments);
+            let _ = compressing.join();
+        }
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    gz_path.into()
+}
+
+/// Gzips `path` next to it as `path.gz` and removes the original
+fn compress(path: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(gz_path(path))?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)
+}
+
+/// Why `fan` isn't simply following its curve, if it isn't
+fn override_reason(status: &Status, fan: &FanStatus) -> Option<String> {
+    if status.emergency {
+        Some(String::from("thermal emergency"))
+    } else if status.power_mode != 0 {
+        Some(String::from("power mode"))
+    } else if status.fans.iter().any(|fan| fan.health == "failed") {
+        Some(String::from("failed fan"))
+    } else if fan.pinned.is_some() {
+        Some(String::from("pinned"))
+    } else {
+        fan.clamped.clone()
+    }
+}
+
+fn csv_header(status: &Status) -> String {
+    let mut columns = vec![
+        String::from("timestamp"),
+        String::from("power_mode"),
+        String::from("profile"),
+    ];
+    for fan in &status.fans {
+        let name = fan.name.to_lowercase();
+        for column in ["temp", "rpm", "boost", "override"] {
+            columns.push(format!("{name}_{column}"));
+        }
+    }
+    columns.join(",")
+}
+
+fn csv_row(status: &Status) -> String {
+    let mut fields = vec![
+        chrono::Local::now().to_rfc3339(),
+        status.power_mode.to_string(),
+        csv_field(&status.profile),
+    ];
+    for fan in &status.fans {
+        fields.push(fan.temp.to_string());
+        fields.push(fan.rpm.to_string());
+        fields.push(fan.boost.to_string());
+        fields.push(csv_field(&override_reason(status, fan).unwrap_or_default()));
+    }
+    fields.join(",")
+}
+
+/// Quoted when it has to be, profile names come from the command line
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_row(status: &Status) -> Value {
+    let fans: Vec<Value> = status
+        .fans
+        .iter()
+        .map(|fan| {
+            json!({
+                "name": fan.name,
+                "sensor_id": fan.sensor_id,
+                "temp": fan.temp,
+                "fan_id": fan.fan_id,
+                "rpm": fan.rpm,
+                "boost": fan.boost,
+                "override": override_reason(status, fan),
+            })
+        })
+        .collect();
+    json!({
+        "timestamp": chrono::Local::now().to_rfc3339(),
+        "power_mode": status.power_mode,
+        "profile": status.profile,
+        "fans": fans,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn status(boost: u8, pinned: Option<u8>) -> Status {
+        let fan = |name: &str, fan_id, sensor_id| FanStatus {
+            name: name.to_string(),
+            fan_id,
+            sensor_id,
+            temp: 40,
+            rpm: boost as i64 * 20,
+            boost,
+            health: String::from("ok"),
+            pinned,
+            clamped: None,
+            watchdog_fired: 0,
+        };
+        Status {
+            power_mode: 0,
+            profile: String::from("default"),
+            emergency: false,
+            fans: vec![fan("CPU", 50, 1), fan("GPU", 51, 6)],
+        }
+    }
+
+    #[test]
+    fn rotates_by_size_and_gzips_the_old_segment() {
+        let dir = std::env::temp_dir().join(format!("awc-recorder-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("telemetry.csv");
+
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::Csv, Some(1), None);
+        recorder.record(&status(90, None));
+        recorder.record(&status(120, Some(120)));
+        drop(recorder);
+
+        let current = fs::read_to_string(&path).unwrap();
+        let mut lines = current.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "timestamp,power_mode,profile,cpu_temp,cpu_rpm,cpu_boost,cpu_override,\
+             gpu_temp,gpu_rpm,gpu_boost,gpu_override"
+        );
+        let row: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(
+            row[1..7],
+            ["0", "default", "40", "2400", "120", "pinned"][..]
+        );
+        assert_eq!(row.len(), 11);
+
+        let segments: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| p != &path)
+            .collect();
+        assert_eq!(segments.len(), 1);
+        let segment = &segments[0];
+        assert!(segment.to_string_lossy().ends_with(".csv.gz"));
+        let mut old = String::new();
+        GzDecoder::new(File::open(segment).unwrap())
+            .read_to_string(&mut old)
+            .unwrap();
+        assert!(old.starts_with("timestamp,"));
+        assert!(old.lines().nth(1).unwrap().ends_with(",90,"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotations_in_the_same_second_keep_every_segment() {
+        let dir = std::env::temp_dir().join(format!("awc-recorder-burst-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("telemetry.jsonl");
+
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::Jsonl, Some(1), None);
+        for boost in 0..4 {
+            recorder.record(&status(boost, None));
+        }
+        drop(recorder);
+
+        let mut boosts: Vec<u64> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| p != &path)
+            .map(|segment| {
+                assert!(segment.to_string_lossy().ends_with(".jsonl.gz"));
+                let mut row = String::new();
+                GzDecoder::new(File::open(segment).unwrap())
+                    .read_to_string(&mut row)
+                    .unwrap();
+                let row: Value = serde_json::from_str(&row).unwrap();
+                row["fans"][0]["boost"].as_u64().unwrap()
+            })
+            .collect();
+        boosts.sort();
+        assert_eq!(boosts, [0, 1, 2]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jsonl_rows_keep_every_fan() {
+        let row = json_row(&status(90, None));
+        assert_eq!(row["profile"], "default");
+        assert_eq!(row["fans"][1]["fan_id"], 51);
+        assert_eq!(row["fans"][0]["boost"], 90);
+        assert_eq!(row["fans"][0]["override"], Value::Null);
+        assert_eq!(
+            RecordFormat::for_path(Path::new("/var/log/awc/telemetry.jsonl")),
+            RecordFormat::Jsonl
+        );
+    }
+}
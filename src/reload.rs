@@ -0,0 +1,308 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use inotify::{Inotify, WatchMask};
+
+use crate::{
+    config::{check_graph, ConfigSource, DeviceInfo},
+    control::{ControlCommand, SharedHandle},
+    controller::{get_coords_from_string, BoostCurve, FanCurve, DEFAULT_PROFILE},
+    rpm_control::{get_calibration_from_string, get_rpm_coords_from_string, RpmTarget},
+    CurveTarget, GraphType,
+};
+
+/// Editors write in bursts, wait for the dust to settle before reloading
+const SETTLE: Duration = Duration::from_millis(250);
+
+pub type Profiles = BTreeMap<String, [FanCurve; 2]>;
+
+/// Where the `default` profile comes from
+#[derive(Debug, Clone)]
+pub enum CurveFile {
+    /// A two line graph file, as given to `--path`
+    Graph(PathBuf),
+    /// The `cpu.graph` and `gpu.graph` of an [`AwcConfig`]
+    Config(ConfigSource),
+}
+
+impl fmt::Display for CurveFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CurveFile::Graph(path) => write!(f, "{}", path.display()),
+            CurveFile::Config(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+/// Every file the curves come from, so they can be read again
+#[derive(Debug, Clone)]
+pub struct CurveSource {
+    pub curves: CurveFile,
+    pub profiles: Vec<(String, PathBuf)>,
+    pub calibration: Option<PathBuf>,
+    pub target: CurveTarget,
+    /// `--graph`, which overrides the config's per device `graph_type`
+    pub graph_type: Option<GraphType>,
+    pub rpm_tolerance: u32,
+    last_good: Arc<Mutex<Option<Profiles>>>,
+}
+
+impl CurveSource {
+    pub fn new(
+        curves: CurveFile,
+        profiles: Vec<(String, PathBuf)>,
+        calibration: Option<PathBuf>,
+        target: CurveTarget,
+        graph_type: Option<GraphType>,
+        rpm_tolerance: u32,
+    ) -> Self {
+        Self {
+            curves,
+            profiles,
+            calibration,
+            target,
+            graph_type,
+            rpm_tolerance,
+            last_good: Arc::default(),
+        }
+    }
+
+    /// Reads and validates every file. Nothing changes unless all of them
+    /// parse, the `--path` or config curves become the `default` profile.
+    pub fn load(&self) -> Result<Profiles, String> {
+        let calibration = match &self.calibration {
+            Some(path) => Some(read(path).and_then(|s| {
+                get_calibration_from_string(&s).map_err(|e| format!("{}: {e}", path.display()))
+            })?),
+            None => None,
+        };
+        let (cpu_cal, gpu_cal) = match calibration {
+            Some((cpu_cal, gpu_cal)) => (Some(cpu_cal), Some(gpu_cal)),
+            None => (None, None),
+        };
+        let graph_type = self.graph_type.unwrap_or(GraphType::Linear);
+        let load_curves = |path: &Path| -> Result<[FanCurve; 2], String> {
+            let buf = read(path)?;
+            let curves = match self.target {
+                CurveTarget::Boost => {
+                    let (cpu_graph, gpu_graph) = get_coords_from_string(&buf)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    let problems = [
+                        check_graph("line 1", &cpu_graph),
+                        check_graph("line 2", &gpu_graph),
+                    ]
+                    .concat();
+                    if !problems.is_empty() {
+                        return Err(format!("{}: {}", path.display(), problems.join(", ")));
+                    }
+                    [
+                        FanCurve::Boost(
+                            BoostCurve::new(cpu_graph, graph_type)
+                                .with_calibration(cpu_cal.clone()),
+                        ),
+                        FanCurve::Boost(
+                            BoostCurve::new(gpu_graph, graph_type)
+                                .with_calibration(gpu_cal.clone()),
+                        ),
+                    ]
+                }
+                CurveTarget::Rpm => {
+                    let (cpu_graph, gpu_graph) = get_rpm_coords_from_string(&buf)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    let tolerance = self.rpm_tolerance;
+                    [
+                        FanCurve::Rpm(RpmTarget::new(
+                            cpu_graph,
+                            graph_type,
+                            cpu_cal.clone(),
+                            tolerance,
+                        )),
+                        FanCurve::Rpm(RpmTarget::new(
+                            gpu_graph,
+                            graph_type,
+                            gpu_cal.clone(),
+                            tolerance,
+                        )),
+                    ]
+                }
+            };
+            Ok(curves)
+        };
+
+        let mut profiles = Profiles::new();
+        let default_curves = match &self.curves {
+            CurveFile::Graph(path) => load_curves(path)?,
+            CurveFile::Config(source) => {
+                let config = source.load()?;
+                let curve = |device: &DeviceInfo, calibration: &Option<_>| {
+                    let graph_type = self.graph_type.unwrap_or(device.graph_type);
+                    FanCurve::Boost(
+                        BoostCurve::new(device.graph.clone(), graph_type)
+                            .with_calibration(Option::clone(calibration)),
+                    )
+                };
+                match self.target {
+                    CurveTarget::Boost => {
+                        [curve(&config.cpu, &cpu_cal), curve(&config.gpu, &gpu_cal)]
+                    }
+                    CurveTarget::Rpm => {
+                        return Err(format!(
+                            "{} holds boost curves, rpm curves have to come from --path",
+                            self.curves
+                        ))
+                    }
+                }
+            }
+        };
+        profiles.insert(String::from(DEFAULT_PROFILE), default_curves);
+        for (name, path) in &self.profiles {
+            profiles.insert(name.clone(), load_curves(path)?);
+        }
+        *self.last_good.lock().unwrap() = Some(profiles.clone());
+        Ok(profiles)
+    }
+
+    /// What the last successful [`load`](Self::load) returned
+    pub fn last_good(&self) -> Option<Profiles> {
+        self.last_good.lock().unwrap().clone()
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = match &self.curves {
+            CurveFile::Graph(path) => vec![path.clone()],
+            CurveFile::Config(source) => source.files.clone(),
+        };
+        paths.extend(self.profiles.iter().map(|(_, path)| path.clone()));
+        paths.extend(self.calibration.clone());
+        paths
+    }
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Asks the controller to reload whenever one of `paths` is written or
+/// replaced. The directories are watched rather than the files, editors
+/// usually save by renaming a new file over the old one.
+pub fn watch_files(paths: Vec<PathBuf>, handle: SharedHandle) -> io::Result<()> {
+    let mut inotify = Inotify::init()?;
+    let mut watched = Vec::new();
+    for path in &paths {
+        let path = fs::canonicalize(path)?;
+        let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let wd = inotify.watches().add(
+            &dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+        )?;
+        watched.push((wd, path));
+    }
+
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let changed = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events.into_iter().any(|event| {
+                    watched
+                        .iter()
+                        .any(|(wd, path)| *wd == event.wd && event.name == path.file_name())
+                }),
+                Err(e) => {
+                    error!("Watching the curve files: {e}");
+                    return;
+                }
+            };
+            if !changed {
+                continue;
+            }
+            thread::sleep(SETTLE);
+            // whatever else arrived meanwhile is covered by this reload
+            while inotify
+                .read_events(&mut buffer)
+                .is_ok_and(|events| events.count() > 0)
+            {}
+            info!("Curve files changed, reloading");
+            match handle.send(ControlCommand::Reload) {
+                Ok(reply) => info!("{reply}"),
+                Err(e) => error!("{e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::{
+        controller::{Controller, ALIEN_DEVICES},
+        emergency::ThermalEmergency,
+        simulated::{drive, with_simulated},
+    };
+
+    #[test]
+    fn an_invalid_reload_keeps_the_old_curves() {
+        with_simulated(|sim| {
+            let dir = std::env::temp_dir().join(format!("awc-{}-reload", process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let config = dir.join("awc.conf");
+            let graph = |points: &str| {
+                format!("{{ graph: [{points}], graph_type: 'linear', sensor: 1, fan: 50 }}")
+            };
+            let write = |cpu: &str| {
+                let gpu = graph("{ temp: 0, fan_boost: 0 }, { temp: 60, fan_boost: 200 }");
+                let text = format!(
+                    "{{ disable_power_mode_on_startup: false, interval: 5, cpu: {}, gpu: {} }}",
+                    graph(cpu),
+                    gpu.replace("sensor: 1, fan: 50", "sensor: 6, fan: 51")
+                );
+                fs::write(&config, text).unwrap();
+            };
+            write("{ temp: 0, fan_boost: 0 }, { temp: 60, fan_boost: 200 }");
+            let source = CurveSource::new(
+                CurveFile::Config(ConfigSource {
+                    files: vec![config.clone()],
+                    sets: vec![],
+                }),
+                vec![],
+                None,
+                CurveTarget::Boost,
+                None,
+                100,
+            );
+            let profiles = source.load().unwrap();
+            let mut controller = Controller::new(
+                ALIEN_DEVICES,
+                profiles[DEFAULT_PROFILE].clone(),
+                ThermalEmergency::new(vec![], Duration::ZERO),
+            );
+            controller.set_curve_source(source);
+            sim.set_temp(1, 30);
+
+            let (falling, empty, boost) = drive(&mut controller, |handle| {
+                write("{ temp: 0, fan_boost: 200 }, { temp: 60, fan_boost: 0 }");
+                let falling = handle.send(ControlCommand::Reload);
+                write("");
+                let empty = handle.send(ControlCommand::Reload);
+                handle.send(ControlCommand::Next).unwrap();
+                (falling, empty, sim.boost(50))
+            });
+
+            assert!(falling
+                .unwrap_err()
+                .contains("cpu.graph boosts may not go down"));
+            assert!(empty.unwrap_err().contains("cpu.graph has no points"));
+            // still on the first curve, 30°C is 100 on it
+            assert_eq!(boost, 100);
+            fs::remove_dir_all(dir).unwrap();
+        });
+    }
+}
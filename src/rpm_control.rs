@@ -0,0 +1,337 @@
+use crate::{
+    controller::{parse_pair, two_lines},
+    GraphType,
+};
+
+/// Rough fan slope used when no calibration table is loaded
+const DEFAULT_RPM_PER_BOOST: i64 = 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RpmCoOrdinates {
+    temp: u8,
+    rpm: u32,
+}
+
+/// One measured point of a fan's boost -> rpm response
+#[derive(Debug, Clone, Copy)]
+pub struct CalibrationPoint {
+    boost: u8,
+    rpm: u32,
+}
+
+/// Drives a single fan towards the rpm its curve asks for.
+///
+/// Every tick the target rpm is read off the curve and the boost is nudged by
+/// the rpm error divided by the fan slope. When a calibration table exists the
+/// boost is kept as the calibrated value plus the correction the loop has
+/// learnt, so a new target jumps straight to its calibrated boost without
+/// losing what the feedback found out about this particular fan.
+#[derive(Debug, Clone)]
+pub struct RpmTarget {
+    graph: Vec<RpmCoOrdinates>,
+    graph_type: GraphType,
+    calibration: Option<Vec<CalibrationPoint>>,
+    tolerance: u32,
+    last_target: Option<u32>,
+    /// Feedback correction on top of the calibrated boost
+    offset: i64,
+}
+
+impl RpmTarget {
+    pub fn new(
+        graph: Vec<RpmCoOrdinates>,
+        graph_type: GraphType,
+        calibration: Option<Vec<CalibrationPoint>>,
+        tolerance: u32,
+    ) -> Self {
+        Self {
+            graph,
+            graph_type,
+            calibration,
+            tolerance,
+            last_target: None,
+            offset: 0,
+        }
+    }
+
+    pub fn last_target(&self) -> Option<u32> {
+        self.last_target
+    }
+
+    /// The rpm the calibration table predicts for `boost`
+    pub fn expected_rpm(&self, boost: u8) -> Option<u32> {
+        self.calibration
+            .as_deref()
+            .map(|calibration| rpm_for_boost(boost, calibration))
+    }
+
+    fn target(&self, temp: u8) -> u32 {
+        match self.graph_type {
+            GraphType::Linear => get_rpm_from_temp_linear(temp, &self.graph),
+            GraphType::Step => get_rpm_from_temp_step(temp, &self.graph),
+        }
+    }
+
+    /// Takes `boost` as what the loop had got to for `temp`, and keeps its
+    /// difference to the calibrated value as the learnt correction
+    pub fn resume(&mut self, temp: u8, boost: u8) -> u8 {
+        let target = self.target(temp);
+        self.last_target = Some(target);
+        if let Some(calibration) = &self.calibration {
+            self.offset = boost as i64 - boost_for_rpm(target, calibration) as i64;
+        }
+        boost
+    }
+
+    pub fn next_boost(&mut self, temp: u8, rpm: i64, current_boost: u8) -> u8 {
+        let target = self.target(temp);
+        let target_changed = self.last_target != Some(target);
+        self.last_target = Some(target);
+
+        if target == 0 {
+            return 0;
+        }
+
+        let calibrated = self
+            .calibration
+            .as_deref()
+            .map(|calibration| boost_for_rpm(target, calibration) as i64);
+
+        if target_changed {
+            if let Some(calibrated) = calibrated {
+                return (calibrated + self.offset).clamp(0, 255) as u8;
+            }
+        }
+
+        let error = target as i64 - rpm;
+        if error.unsigned_abs() <= self.tolerance as u64 {
+            return current_boost;
+        }
+
+        let slope = self
+            .calibration
+            .as_deref()
+            .map(|c| rpm_per_boost(current_boost, c))
+            .unwrap_or(DEFAULT_RPM_PER_BOOST);
+        let mut step = error / slope;
+        if step == 0 {
+            step = error.signum();
+        }
+        let next = (current_boost as i64 + step).clamp(0, 255);
+        if let Some(calibrated) = calibrated {
+            self.offset = next - calibrated;
+        }
+        next as u8
+    }
+}
+
+pub fn get_rpm_coords_from_string(
+    s: &str,
+) -> Result<(Vec<RpmCoOrdinates>, Vec<RpmCoOrdinates>), String> {
+    let (line0, line1) = two_lines(s)?;
+    let cpu = line_to_rpm_coords(line0).map_err(|e| format!("line 1: {e}"))?;
+    let gpu = line_to_rpm_coords(line1).map_err(|e| format!("line 2: {e}"))?;
+    Ok((cpu, gpu))
+}
+
+pub fn get_calibration_from_string(
+    s: &str,
+) -> Result<(Vec<CalibrationPoint>, Vec<CalibrationPoint>), String> {
+    let (line0, line1) = two_lines(s)?;
+    let cpu = line_to_calibration(line0).map_err(|e| format!("line 1: {e}"))?;
+    let gpu = line_to_calibration(line1).map_err(|e| format!("line 2: {e}"))?;
+    Ok((cpu, gpu))
+}
+
+fn line_to_rpm_coords(line: &str) -> Result<Vec<RpmCoOrdinates>, String> {
+    let mut v = Vec::<RpmCoOrdinates>::with_capacity(32);
+    for coord in line.split(',') {
+        let (temp, rpm) = parse_pair(coord)?;
+        if let Some(last) = v.last() {
+            if last.temp >= temp {
+                return Err(String::from("Temps must be ascending order!!!"));
+            }
+        }
+        v.push(RpmCoOrdinates { temp, rpm });
+    }
+    Ok(v)
+}
+
+fn line_to_calibration(line: &str) -> Result<Vec<CalibrationPoint>, String> {
+    let mut v = Vec::<CalibrationPoint>::with_capacity(32);
+    for coord in line.split(',') {
+        let (boost, rpm) = parse_pair(coord)?;
+        if let Some(last) = v.last() {
+            if last.boost >= boost || last.rpm > rpm {
+                return Err(String::from(
+                    "Calibration boosts and rpms must be ascending order!!!",
+                ));
+            }
+        }
+        v.push(CalibrationPoint { boost, rpm });
+    }
+    Ok(v)
+}
+
+fn get_rpm_from_temp_step(temp: u8, coords: &[RpmCoOrdinates]) -> u32 {
+    for coord in coords {
+        if temp < coord.temp {
+            return coord.rpm;
+        }
+    }
+    coords.last().unwrap().rpm
+}
+
+fn get_rpm_from_temp_linear(temp: u8, coords: &[RpmCoOrdinates]) -> u32 {
+    for w in coords.windows(2) {
+        let (a, b) = (&w[0], &w[1]);
+        if temp >= a.temp && temp < b.temp {
+            let t = (temp - a.temp) as i64;
+            let dt = (b.temp - a.temp) as i64;
+            return (a.rpm as i64 + t * (b.rpm as i64 - a.rpm as i64) / dt) as u32;
+        }
+    }
+    coords.last().unwrap().rpm
+}
+
+/// Inverse lookup of the calibration table
+fn boost_for_rpm(rpm: u32, calibration: &[CalibrationPoint]) -> u8 {
+    let first = calibration.first().unwrap();
+    if rpm <= first.rpm {
+        return first.boost;
+    }
+    for w in calibration.windows(2) {
+        let (a, b) = (&w[0], &w[1]);
+        if rpm >= a.rpm && rpm < b.rpm {
+            let r = (rpm - a.rpm) as i64;
+            let dr = (b.rpm - a.rpm) as i64;
+            return (a.boost as i64 + r * (b.boost as i64 - a.boost as i64) / dr) as u8;
+        }
+    }
+    calibration.last().unwrap().boost
+}
+
+pub(crate) fn rpm_for_boost(boost: u8, calibration: &[CalibrationPoint]) -> u32 {
+    let first = calibration.first().unwrap();
+    if boost <= first.boost {
+        return first.rpm;
+    }
+    for w in calibration.windows(2) {
+        let (a, b) = (&w[0], &w[1]);
+        if boost >= a.boost && boost < b.boost {
+            let x = (boost - a.boost) as i64;
+            let dx = (b.boost - a.boost) as i64;
+            return (a.rpm as i64 + x * (b.rpm as i64 - a.rpm as i64) / dx) as u32;
+        }
+    }
+    calibration.last().unwrap().rpm
+}
+
+/// Local slope of the calibration table around `boost`
+fn rpm_per_boost(boost: u8, calibration: &[CalibrationPoint]) -> i64 {
+    let segment = calibration
+        .windows(2)
+        .find(|w| boost < w[1].boost)
+        .or_else(|| calibration.windows(2).last());
+    match segment {
+        Some(w) => {
+            let slope = (w[1].rpm - w[0].rpm) as i64 / (w[1].boost - w[0].boost) as i64;
+            slope.max(1)
+        }
+        None => DEFAULT_RPM_PER_BOOST,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(graph: &str, calibration: Option<&str>, tolerance: u32) -> RpmTarget {
+        let graph = line_to_rpm_coords(graph).unwrap();
+        let calibration = calibration.map(|c| line_to_calibration(c).unwrap());
+        RpmTarget::new(graph, GraphType::Linear, calibration, tolerance)
+    }
+
+    #[test]
+    fn feedback_nudges_by_the_error_outside_the_deadband() {
+        // 30°C is 1500 rpm, at the default 20 rpm per boost
+        let mut fan = target("(0 0), (60 3000)", None, 100);
+        assert_eq!(fan.next_boost(30, 0, 0), 75);
+        assert_eq!(fan.last_target(), Some(1500));
+
+        assert_eq!(fan.next_boost(30, 1450, 75), 75);
+        assert_eq!(fan.next_boost(30, 1600, 75), 75);
+        assert_eq!(fan.next_boost(30, 1000, 75), 100);
+        assert_eq!(fan.next_boost(30, 1700, 100), 90);
+        assert_eq!(fan.next_boost(30, 0, 250), 255);
+
+        // errors under one boost step still move it by one
+        let mut fine = target("(0 0), (60 3000)", None, 0);
+        assert_eq!(fine.next_boost(30, 1490, 80), 81);
+        assert_eq!(fine.next_boost(30, 1510, 80), 79);
+
+        let mut off = target("(0 0), (20 0), (60 3000)", None, 100);
+        assert_eq!(off.next_boost(10, 900, 40), 0);
+    }
+
+    #[test]
+    fn calibration_jumps_to_a_new_target_then_feeds_back() {
+        let calibration = "(0 0), (100 2000), (200 3000)";
+        let mut fan = target("(0 0), (60 3000)", Some(calibration), 100);
+        assert_eq!(fan.next_boost(30, 0, 0), 75);
+        // same target, the table's slope at 75 is 20 rpm per boost
+        assert_eq!(fan.next_boost(30, 1000, 75), 100);
+        // the new target's calibrated 150 keeps the learnt +25
+        assert_eq!(fan.next_boost(50, 1000, 100), 175);
+        // and 10 rpm per boost past 100
+        assert_eq!(fan.next_boost(50, 2000, 175), 225);
+        assert_eq!(fan.expected_rpm(150), Some(2500));
+        assert_eq!(
+            target("(0 0), (60 3000)", None, 100).expected_rpm(150),
+            None
+        );
+    }
+
+    #[test]
+    fn feedback_correction_survives_temperature_drift() {
+        // this fan only spins half as fast as the table claims
+        let calibration = "(0 0), (100 2000), (200 4000)";
+        let mut fan = target("(0 0), (60 3000)", Some(calibration), 50);
+        assert_eq!(fan.next_boost(30, 0, 0), 75);
+        assert_eq!(fan.next_boost(30, 750, 75), 112);
+        assert_eq!(fan.next_boost(30, 1500, 112), 112);
+
+        // 1°C of drift moves the target to 1550, calibrated at 77
+        assert_eq!(fan.next_boost(31, 1500, 112), 114);
+        assert_eq!(fan.next_boost(30, 1550, 114), 112);
+    }
+
+    #[test]
+    fn a_resumed_boost_becomes_the_correction() {
+        let calibration = "(0 0), (100 2000), (200 4000)";
+        let mut fan = target("(0 0), (60 3000)", Some(calibration), 50);
+        assert_eq!(fan.resume(30, 112), 112);
+        assert_eq!(fan.last_target(), Some(1500));
+        // 1550 rpm is calibrated at 77, plus the resumed +37
+        assert_eq!(fan.next_boost(31, 1500, 112), 114);
+    }
+
+    #[test]
+    fn calibration_lookups_interpolate_and_clamp() {
+        let calibration = line_to_calibration("(20 400), (100 2000), (200 3000)").unwrap();
+        assert_eq!(boost_for_rpm(0, &calibration), 20);
+        assert_eq!(boost_for_rpm(1200, &calibration), 60);
+        assert_eq!(boost_for_rpm(2500, &calibration), 150);
+        assert_eq!(boost_for_rpm(9000, &calibration), 200);
+
+        assert_eq!(rpm_for_boost(0, &calibration), 400);
+        assert_eq!(rpm_for_boost(60, &calibration), 1200);
+        assert_eq!(rpm_for_boost(255, &calibration), 3000);
+
+        assert_eq!(rpm_per_boost(50, &calibration), 20);
+        assert_eq!(rpm_per_boost(150, &calibration), 10);
+        assert_eq!(rpm_per_boost(255, &calibration), 10);
+        let single = line_to_calibration("(100 2000)").unwrap();
+        assert_eq!(rpm_per_boost(100, &single), DEFAULT_RPM_PER_BOOST);
+    }
+}
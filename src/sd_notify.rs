@@ -0,0 +1,147 @@
+use std::{
+    env, io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::Duration,
+};
+
+/// Talks the systemd notify protocol over `$NOTIFY_SOCKET`
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// `path` may name an abstract socket with a leading `@`, like systemd does.
+    /// `watchdog` is the timeout systemd enforces, pings go out twice as often
+    pub fn new(path: &str, watchdog: Option<Duration>) -> io::Result<Self> {
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+            watchdog,
+        })
+    }
+
+    /// `None` unless we were started by systemd with `Type=notify`
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        let watchdog_pid_matches = env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_none_or(|pid| pid == process::id());
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| watchdog_pid_matches)
+            .map(Duration::from_micros);
+        match Self::new(&path, watchdog) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                error!("NOTIFY_SOCKET {path}: {e}");
+                None
+            }
+        }
+    }
+
+    /// How often to send `WATCHDOG=1`, if systemd watches us
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+
+    pub fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            error!("sd_notify {state:?}: {e}");
+        }
+    }
+
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={status}"));
+    }
+
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, thread};
+
+    use super::*;
+    use crate::simulated::{drive, test_controller, with_simulated};
+
+    /// A stand-in for systemd's notify socket
+    fn bind_notify_socket(name: &str) -> (UnixDatagram, PathBuf) {
+        let path = env::temp_dir().join(format!("awc-{}-{name}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (socket, path)
+    }
+
+    fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = [0u8; 1024];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn sends_datagrams_to_the_notify_socket() {
+        let (socket, path) = bind_notify_socket("plain");
+        let notifier = Notifier::new(path.to_str().unwrap(), None).unwrap();
+
+        notifier.ready();
+        notifier.status("all good");
+
+        assert_eq!(recv(&socket), "READY=1");
+        assert_eq!(recv(&socket), "STATUS=all good");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn watch_reports_ready_status_and_watchdog_pings() {
+        with_simulated(|_| {
+            let (socket, path) = bind_notify_socket("watch");
+            let notifier =
+                Notifier::new(path.to_str().unwrap(), Some(Duration::from_millis(100))).unwrap();
+            let mut controller = test_controller();
+            controller.set_notifier(notifier);
+
+            drive(&mut controller, |_| {
+                thread::sleep(Duration::from_millis(300))
+            });
+
+            assert_eq!(recv(&socket), "READY=1");
+            assert!(recv(&socket).starts_with("STATUS="));
+            let mut rest = Vec::new();
+            loop {
+                let message = recv(&socket);
+                if message == "STOPPING=1" {
+                    break;
+                }
+                rest.push(message);
+            }
+            assert!(rest.iter().any(|message| message == "WATCHDOG=1"));
+            fs::remove_file(path).unwrap();
+        });
+    }
+}
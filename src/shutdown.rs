@@ -0,0 +1,113 @@
+use std::{
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use crate::controller::{get_power_mode, set_both_fan_boosts, set_power_mode, Devices};
+
+/// The fans awc took over and the power mode found then
+static ORIGINAL: OnceLock<(Devices, u8)> = OnceLock::new();
+/// Set while restoring, so a panic inside the restore doesn't recurse
+static RESTORING: AtomicBool = AtomicBool::new(false);
+
+/// Hands the fans back to the firmware: boosts to 0 (auto) and the power mode
+/// back to what it was before awc started
+pub fn restore_firmware_control(devices: &Devices, original_power_mode: u8) {
+    if RESTORING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    info!("Restoring firmware fan control");
+    set_both_fan_boosts(devices, 0);
+    if get_power_mode() != original_power_mode as i64 {
+        set_power_mode(original_power_mode);
+    }
+    RESTORING.store(false, Ordering::SeqCst);
+}
+
+/// Restores the fans and power mode recorded by [`install_exit_handlers`],
+/// for exits that can't go through the controller
+pub fn restore_original() {
+    if let Some((devices, mode)) = ORIGINAL.get() {
+        restore_firmware_control(devices, *mode);
+    }
+}
+
+/// Records the fans and power mode to go back to and makes panics restore
+/// firmware fan control before the process goes away. Signals are handled in
+/// [`crate::signals`]. Only the first call records the power mode, so pausing
+/// and resuming the watch keeps the real pre-start value.
+pub fn install_exit_handlers(devices: Devices, original_power_mode: u8) {
+    if ORIGINAL.set((devices, original_power_mode)).is_err() {
+        return;
+    }
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_original();
+        default_hook(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        control::ControlCommand,
+        controller::ALIEN_DEVICES,
+        simulated::{drive, test_controller, with_simulated},
+    };
+
+    #[test]
+    fn restore_resets_boosts_and_power_mode() {
+        with_simulated(|sim| {
+            set_both_fan_boosts(&ALIEN_DEVICES, 200);
+            sim.set_power_mode(0);
+
+            restore_firmware_control(&ALIEN_DEVICES, 0xab);
+
+            assert_eq!(sim.boost(50), 0);
+            assert_eq!(sim.boost(51), 0);
+            assert_eq!(sim.power_mode(), 0xab);
+        });
+    }
+
+    #[test]
+    fn quitting_the_watch_restores_the_pre_start_power_mode() {
+        with_simulated(|sim| {
+            sim.set_temp(1, 90);
+            sim.set_temp(6, 90);
+
+            let quit = drive(&mut test_controller(), |handle| {
+                handle.send(ControlCommand::Quit)
+            });
+
+            assert!(quit.is_ok());
+            assert_eq!(sim.boost(50), 0);
+            assert_eq!(sim.boost(51), 0);
+            assert_eq!(sim.power_mode(), 0);
+        });
+    }
+
+    #[test]
+    fn panic_in_watch_thread_restores_firmware_control() {
+        with_simulated(|sim| {
+            install_exit_handlers(ALIEN_DEVICES, 0);
+            let result = thread::spawn(|| {
+                set_both_fan_boosts(&ALIEN_DEVICES, 255);
+                set_power_mode(0xab);
+                panic!("controller blew up");
+            })
+            .join();
+
+            assert!(result.is_err());
+            assert_eq!(sim.boost(50), 0);
+            assert_eq!(sim.boost(51), 0);
+            assert_eq!(sim.power_mode(), 0);
+        });
+    }
+}
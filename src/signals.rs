@@ -0,0 +1,57 @@
+use std::{io, process, sync::mpsc, thread, time::Duration};
+
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
+
+use crate::{
+    control::{ControlCommand, SharedHandle},
+    shutdown::restore_original,
+};
+
+/// How long a stopping controller gets to hand the fans back
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// SIGINT and SIGTERM stop the watch cleanly, SIGHUP reloads and SIGUSR1
+/// shows the current info
+pub fn spawn_signal_thread(shared: SharedHandle) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let result = match signal {
+                SIGHUP => shared.send(ControlCommand::Reload),
+                SIGUSR1 => shared.send(ControlCommand::ShowInfo),
+                _ => stop(&shared),
+            };
+            match result {
+                Ok(reply) => info!("{reply}"),
+                Err(e) => error!("{e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn stop(shared: &SharedHandle) -> ! {
+    info!("Stopping...");
+    let (tx, rx) = mpsc::channel();
+    let shared = shared.clone();
+    thread::spawn(move || tx.send(shared.send(ControlCommand::Quit)));
+    match rx.recv_timeout(STOP_TIMEOUT) {
+        Ok(Ok(reply)) => {
+            info!("{reply}");
+            process::exit(0);
+        }
+        // paused, the fans were handed back already but it doesn't hurt
+        Ok(Err(_)) => {
+            restore_original();
+            process::exit(0);
+        }
+        Err(_) => {
+            error!("Controller didn't stop in time, restoring the fans directly");
+            restore_original();
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+#[cfg(test)]
+use std::time::Duration;
+
+#[cfg(test)]
+use crate::{
+    control::{self, ControlCommand, ControlHandle, SharedHandle},
+    controller::{line_to_coords, BoostCurve, Controller, FanCurve, ALIEN_DEVICES},
+    emergency::ThermalEmergency,
+    socket,
+    watchdog::{RecoveryAction, Watchdog},
+    GraphType,
+};
+
+/// When set, every WMAX call is answered by this instead of /proc/acpi/call
+static SIMULATED: RwLock<Option<Arc<SimulatedBackend>>> = RwLock::new(None);
+
+/// Rpm a healthy simulated fan spins at per unit of boost
+const RPM_PER_BOOST: i64 = 20;
+/// The firmware's function table: fans, then sensors (0x100 + id), then power modes
+const FUNCTIONS: [i64; 6] = [50, 51, 0x101, 0x106, 0xa0, 0xab];
+/// `(fan_id, sen_id)` pairs the firmware reports
+const FAN_SENSORS: [(u8, u8); 2] = [(50, 1), (51, 6)];
+
+#[derive(Debug)]
+struct SimulatedState {
+    power_mode: u8,
+    boosts: BTreeMap<u8, u8>,
+    temps: BTreeMap<u8, i64>,
+    /// Fans that report this rpm no matter their boost
+    stuck_rpms: BTreeMap<u8, i64>,
+    /// Every boost written, by fan id
+    written: BTreeMap<u8, Vec<u8>>,
+}
+
+/// An in-memory stand-in for the Alienware WMAX interface, good enough to run
+/// the controller without the hardware, e.g. with `--simulate` or in tests
+#[derive(Debug)]
+pub struct SimulatedBackend {
+    state: Mutex<SimulatedState>,
+}
+
+impl SimulatedBackend {
+    /// Two fans, 50 and 51, cooled by sensors 1 and 6 like the real machine
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(SimulatedState {
+                power_mode: 0,
+                boosts: BTreeMap::from([(50, 0), (51, 0)]),
+                temps: BTreeMap::from([(1, 40), (6, 40)]),
+                stuck_rpms: BTreeMap::new(),
+                written: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn power_mode(&self) -> u8 {
+        self.state.lock().unwrap().power_mode
+    }
+
+    pub fn set_power_mode(&self, mode: u8) {
+        self.state.lock().unwrap().power_mode = mode;
+    }
+
+    pub fn boost(&self, fan_id: u8) -> u8 {
+        self.state.lock().unwrap().boosts[&fan_id]
+    }
+
+    pub fn set_temp(&self, sen_id: u8, temp: i64) {
+        self.state.lock().unwrap().temps.insert(sen_id, temp);
+    }
+
+    /// Every boost written to `fan_id` so far, oldest first
+    #[cfg(test)]
+    pub fn written(&self, fan_id: u8) -> Vec<u8> {
+        self.state
+            .lock()
+            .unwrap()
+            .written
+            .get(&fan_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Makes a fan report `rpm` regardless of its boost, `None` heals it
+    pub fn set_stuck_rpm(&self, fan_id: u8, rpm: Option<i64>) {
+        let mut state = self.state.lock().unwrap();
+        match rpm {
+            Some(rpm) => state.stuck_rpms.insert(fan_id, rpm),
+            None => state.stuck_rpms.remove(&fan_id),
+        };
+    }
+
+    pub fn call(&self, cmd: u8, sub: u8, arg0: u8, arg1: u8) -> i64 {
+        let mut state = self.state.lock().unwrap();
+        match (cmd, sub) {
+            (0x14, 0xb) => state.power_mode as i64,
+            (0x15, 1) => {
+                state.power_mode = arg0;
+                0
+            }
+            (0x14, 0xc) => state.boosts.get(&arg0).copied().unwrap_or_default() as i64,
+            (0x15, 2) => {
+                state.boosts.insert(arg0, arg1);
+                state.written.entry(arg0).or_default().push(arg1);
+                0
+            }
+            (0x14, 5) => match state.stuck_rpms.get(&arg0) {
+                Some(rpm) => *rpm,
+                None => state.boosts.get(&arg0).copied().unwrap_or_default() as i64 * RPM_PER_BOOST,
+            },
+            (0x14, 4) => state.temps.get(&arg0).copied().unwrap_or_default(),
+            (0x14, 3) => FUNCTIONS.get(arg0 as usize).copied().unwrap_or(-1),
+            (0x13, 2) => FAN_SENSORS
+                .iter()
+                .find(|(fan_id, _)| *fan_id == arg0)
+                .map_or(-1, |(_, sen_id)| *sen_id as i64),
+            _ => -1,
+        }
+    }
+}
+
+pub fn install(backend: Arc<SimulatedBackend>) {
+    *SIMULATED.write().unwrap() = Some(backend);
+}
+
+pub fn uninstall() {
+    *SIMULATED.write().unwrap() = None;
+}
+
+pub fn current() -> Option<Arc<SimulatedBackend>> {
+    SIMULATED.read().unwrap().clone()
+}
+
+/// The simulated backend is process wide, tests using it take turns
+#[cfg(test)]
+static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Runs `f` with a fresh simulated backend installed
+#[cfg(test)]
+pub fn with_simulated(f: impl FnOnce(&SimulatedBackend)) {
+    let _guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let sim = Arc::new(SimulatedBackend::new());
+    install(sim.clone());
+    f(&sim);
+    uninstall();
+}
+
+/// Both fans on a linear `(0 0), (60 200)`, the controller most tests start from
+#[cfg(test)]
+pub fn test_controller() -> Controller {
+    let graph = line_to_coords("(0 0), (60 200)").unwrap();
+    let curve = FanCurve::Boost(BoostCurve::new(graph, GraphType::Linear));
+    Controller::new(
+        ALIEN_DEVICES,
+        [curve.clone(), curve],
+        ThermalEmergency::new(vec![], Duration::ZERO),
+    )
+}
+
+/// Watches with `controller` on another thread while `client` drives it,
+/// then quits unless `client` did. The interval is an hour, so only the
+/// first tick and commands make it tick, and the watchdog waits three.
+#[cfg(test)]
+pub fn drive<T>(controller: &mut Controller, client: impl FnOnce(&ControlHandle) -> T) -> T {
+    let watchdog = Watchdog::new(Duration::from_secs(3 * 3600), 0, RecoveryAction::PulseZero);
+    drive_with(controller, &watchdog, client)
+}
+
+/// [`drive`] with another watchdog
+#[cfg(test)]
+pub fn drive_with<T>(
+    controller: &mut Controller,
+    watchdog: &Watchdog,
+    client: impl FnOnce(&ControlHandle) -> T,
+) -> T {
+    std::thread::scope(|scope| {
+        let (handle, commands) = control::channel();
+        scope.spawn(move || controller.watch(3600, watchdog, &commands));
+        let result = client(&handle);
+        let _ = handle.send(ControlCommand::Quit);
+        result
+    })
+}
+
+/// [`drive`] with the control socket served on a temporary path, which is
+/// what `client` gets to connect to
+#[cfg(test)]
+pub fn drive_socket<T>(controller: &mut Controller, client: impl FnOnce(&str) -> T) -> T {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "awc-{}-{}.sock",
+        std::process::id(),
+        SOCKETS.fetch_add(1, Ordering::SeqCst)
+    ));
+    let path = path.to_str().unwrap();
+    let shared = SharedHandle::default();
+    socket::serve(path, shared.clone()).unwrap();
+    let result = drive(controller, |handle| {
+        shared.set(Some(handle.clone()));
+        client(path)
+    });
+    shared.set(None);
+    let _ = std::fs::remove_file(path);
+    result
+}
@@ -0,0 +1,327 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{self as unix_fs, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    control::{ControlCommand, Reply, SharedHandle},
+    feed,
+};
+
+pub const DEFAULT_SOCKET_PATH: &str = "/run/awc.sock";
+/// Group that may use the socket besides root, when it exists
+const SOCKET_GROUP: &str = "awc";
+
+/// One line of the control protocol, e.g. `{"cmd": "boost", "fan": 50, "boost": 200}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    Status,
+    Temps,
+    Rpms,
+    Boosts,
+    /// Sets the power mode, or toggles it without `enabled`
+    PowerMode {
+        enabled: Option<bool>,
+    },
+    Profile {
+        name: String,
+    },
+    /// Pins a fan, for `seconds` if given
+    Boost {
+        fan: u8,
+        boost: u8,
+        seconds: Option<u64>,
+    },
+    /// Hands a fan, or every fan, back to its curve
+    Auto {
+        fan: Option<u8>,
+    },
+    Reload,
+    /// Turns the connection into a stream of events, plus per-tick samples
+    /// unless `samples` is false
+    Subscribe {
+        samples: Option<bool>,
+    },
+}
+
+/// Listens on `path` for line delimited JSON requests, answering each with a
+/// `{"ok": true, "result": ...}` or `{"ok": false, "error": "..."}` line
+pub fn serve(path: &str, handle: SharedHandle) -> io::Result<()> {
+    if Path::new(path).exists() {
+        // a daemon that is still alive would answer, a stale socket refuses
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{path} is in use by another awc"),
+            ));
+        }
+        // only ever remove a socket, not whatever --socket was pointed at
+        if !fs::metadata(path)?.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path} exists and is not a socket"),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+    if let Some(gid) = group_id(SOCKET_GROUP) {
+        if let Err(e) = unix_fs::chown(path, None, Some(gid)) {
+            warn!("Can't give {path} to the {SOCKET_GROUP} group: {e}");
+        }
+    }
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handle = handle.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_client(stream, handle) {
+                            warn!("Control socket client: {e}");
+                        }
+                    });
+                }
+                Err(e) => error!("Control socket: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn serve_client(stream: UnixStream, handle: SharedHandle) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe { samples }) => {
+                return stream_feed(writer, samples.unwrap_or(true))
+            }
+            Ok(request) => handle_request(request, &handle),
+            Err(e) => json!({ "ok": false, "error": e.to_string() }),
+        };
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+/// Writes one `{"sample": ...}` or `{"event": ...}` line per item until the
+/// client goes away. Only this thread waits on a slow client, the feed itself
+/// skips samples for it or drops it.
+fn stream_feed(mut writer: UnixStream, samples: bool) -> io::Result<()> {
+    let feed = feed::subscribe(samples);
+    writeln!(writer, "{}", json!({ "ok": true, "result": "subscribed" }))?;
+    for item in feed {
+        writeln!(writer, "{}", serde_json::to_value(item).unwrap())?;
+    }
+    Ok(())
+}
+
+fn handle_request(request: Request, handle: &SharedHandle) -> Value {
+    let command = match request {
+        Request::Status | Request::Temps | Request::Rpms | Request::Boosts => {
+            ControlCommand::Status
+        }
+        Request::PowerMode { enabled: None } => ControlCommand::TogglePowerMode,
+        Request::PowerMode {
+            enabled: Some(enabled),
+        } => ControlCommand::SetPowerMode(enabled),
+        Request::Profile { ref name } => ControlCommand::SetProfile(name.clone()),
+        Request::Boost {
+            fan,
+            boost,
+            seconds,
+        } => ControlCommand::SetBoost {
+            fan_id: fan,
+            boost,
+            duration: seconds.map(Duration::from_secs),
+        },
+        Request::Auto { fan } => ControlCommand::ClearBoost { fan_id: fan },
+        Request::Reload => ControlCommand::Reload,
+        Request::Subscribe { .. } => unreachable!("handled by serve_client"),
+    };
+
+    let reply = match handle.send(command) {
+        Ok(reply) => reply,
+        Err(e) => return json!({ "ok": false, "error": e }),
+    };
+    let result = match (&request, reply) {
+        (Request::Temps, Reply::Status(status)) => status
+            .fans
+            .iter()
+            .map(|fan| json!({ "name": fan.name, "sensor": fan.sensor_id, "temp": fan.temp }))
+            .collect(),
+        (Request::Rpms, Reply::Status(status)) => status
+            .fans
+            .iter()
+            .map(|fan| json!({ "name": fan.name, "fan": fan.fan_id, "rpm": fan.rpm }))
+            .collect(),
+        (Request::Boosts, Reply::Status(status)) => status
+            .fans
+            .iter()
+            .map(|fan| {
+                json!({
+                    "name": fan.name,
+                    "fan": fan.fan_id,
+                    "boost": fan.boost,
+                    "pinned": fan.pinned,
+                    "clamped": fan.clamped,
+                })
+            })
+            .collect(),
+        (_, reply) => serde_json::to_value(reply).unwrap(),
+    };
+    json!({ "ok": true, "result": result })
+}
+
+/// The gid of group `name` in /etc/group
+fn group_id(name: &str) -> Option<u32> {
+    let groups = fs::read_to_string("/etc/group").ok()?;
+    groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulated::{drive_socket, test_controller, with_simulated};
+
+    /// One connection, talking a JSON line at a time
+    struct Line {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream,
+    }
+
+    impl Line {
+        fn connect(path: &str) -> Self {
+            let writer = UnixStream::connect(path).unwrap();
+            let reader = BufReader::new(writer.try_clone().unwrap());
+            Self { reader, writer }
+        }
+
+        fn send(&mut self, request: &str) {
+            writeln!(self.writer, "{request}").unwrap();
+        }
+
+        fn read(&mut self) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        fn ask(&mut self, request: &str) -> Value {
+            self.send(request);
+            self.read()
+        }
+    }
+
+    #[test]
+    fn requests_are_answered_a_line_each() {
+        with_simulated(|sim| {
+            sim.set_temp(6, 30);
+            let answers = drive_socket(&mut test_controller(), |path| {
+                let mut line = Line::connect(path);
+                [
+                    line.ask(r#"{"cmd": "temps"}"#),
+                    line.ask(r#"{"cmd": "boost", "fan": 50, "boost": 200}"#),
+                    line.ask(r#"{"cmd": "boosts"}"#),
+                    line.ask(r#"{"cmd": "rpms"}"#),
+                    line.ask(r#"{"cmd": "status"}"#),
+                    line.ask(r#"{"cmd": "boost", "fan": 7, "boost": 200}"#),
+                    line.ask(r#"{"cmd": "boost", "fan": 50}"#),
+                    line.ask("not json"),
+                ]
+            });
+            let [temps, pinned, boosts, rpms, status, unknown, missing, garbage] = answers;
+
+            assert_eq!(
+                temps,
+                json!({ "ok": true, "result": [
+                    { "name": "CPU", "sensor": 1, "temp": 40 },
+                    { "name": "GPU", "sensor": 6, "temp": 30 },
+                ]})
+            );
+            assert_eq!(
+                pinned,
+                json!({ "ok": true, "result": "Fan #50 pinned to 200" })
+            );
+            assert_eq!(
+                boosts["result"][0],
+                json!({ "name": "CPU", "fan": 50, "boost": 200, "pinned": 200, "clamped": null })
+            );
+            assert_eq!(boosts["result"][1]["pinned"], Value::Null);
+            assert_eq!(
+                rpms["result"][0],
+                json!({ "name": "CPU", "fan": 50, "rpm": 4000 })
+            );
+            assert_eq!(status["result"]["profile"], "default");
+            assert_eq!(status["result"]["fans"][1]["boost"], 100);
+            assert_eq!(unknown, json!({ "ok": false, "error": "Unknown fan #7" }));
+            assert_eq!(missing["ok"], false);
+            assert!(missing["error"].as_str().unwrap().contains("boost"));
+            assert_eq!(garbage["ok"], false);
+        });
+    }
+
+    #[test]
+    fn subscribers_get_samples_and_events() {
+        with_simulated(|_| {
+            let (subscribed, items) = drive_socket(&mut test_controller(), |path| {
+                let mut feed = Line::connect(path);
+                let subscribed = feed.ask(r#"{"cmd": "subscribe"}"#);
+                let mut control = Line::connect(path);
+                control.ask(r#"{"cmd": "profile", "name": "default"}"#);
+                // the first tick may or may not be sampled before the
+                // profile change, the change itself always is
+                let mut items = vec![];
+                while !items.iter().any(|item: &Value| item["event"].is_object()) {
+                    items.push(feed.read());
+                }
+                items.push(feed.read());
+                (subscribed, items)
+            });
+
+            assert_eq!(subscribed, json!({ "ok": true, "result": "subscribed" }));
+            let event = items.iter().find(|item| item["event"].is_object()).unwrap();
+            assert_eq!(
+                event["event"],
+                json!({ "event": "profile-changed", "profile": "default" })
+            );
+            let sample = items.last().unwrap();
+            assert_eq!(sample["sample"]["profile"], "default");
+            assert_eq!(sample["sample"]["fans"][0]["fan_id"], 50);
+        });
+    }
+
+    #[test]
+    fn only_a_stale_socket_is_replaced() {
+        let path = std::env::temp_dir().join(format!("awc-{}-not-a-socket", std::process::id()));
+        fs::write(&path, "keep me").unwrap();
+
+        let error = serve(path.to_str().unwrap(), SharedHandle::default()).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+        fs::remove_file(path).unwrap();
+    }
+}
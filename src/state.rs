@@ -0,0 +1,204 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_STATE_PATH: &str = "/var/lib/awc/state.json";
+
+/// What a restarted awc needs to carry on where the last one stopped
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub profile: String,
+    /// Manual overrides, by fan id
+    pub pinned: BTreeMap<u8, Pin>,
+    /// Power mode found before awc took over. Cleared on a clean shutdown,
+    /// which restores it, so a value here means the last run didn't get to.
+    pub original_power_mode: Option<u8>,
+    /// Last boost written, by fan id, where rpm curves carry on from
+    pub boosts: BTreeMap<u8, u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    pub boost: u8,
+    /// Unix time a temporary pin runs out at
+    pub until: Option<u64>,
+}
+
+/// `None` if there is no state yet, or it can't be read
+pub fn load(path: &Path) -> Option<State> {
+    let buf = match fs::read_to_string(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Ignoring state file {}: {e}", path.display());
+            return None;
+        }
+    };
+    match serde_json::from_str(&buf) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("Ignoring state file {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Writes `state` through a temporary file, a crash mid-write leaves the
+/// previous state intact
+pub fn save(path: &Path, state: &State) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(state).unwrap())?;
+    fs::rename(tmp, path)
+}
+
+pub fn instant_to_unix(instant: Instant) -> u64 {
+    let from_now = instant.saturating_duration_since(Instant::now());
+    SystemTime::now()
+        .checked_add(from_now)
+        .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+        .map_or(u64::MAX, |since| since.as_secs())
+}
+
+/// `None` once `unix` is in the past, or too far ahead to be an `Instant`
+pub fn unix_to_instant(unix: u64) -> Option<Instant> {
+    let at = UNIX_EPOCH.checked_add(Duration::from_secs(unix))?;
+    let from_now = at.duration_since(SystemTime::now()).ok()?;
+    Instant::now().checked_add(from_now)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::{
+        control::ControlCommand,
+        controller::{line_to_coords, BoostCurve, Controller, FanCurve, ALIEN_DEVICES},
+        emergency::ThermalEmergency,
+        rpm_control::{get_rpm_coords_from_string, RpmTarget},
+        simulated::{drive, test_controller, with_simulated},
+        GraphType,
+    };
+
+    fn controller() -> Controller {
+        let mut controller = test_controller();
+        let silent = || {
+            let graph = line_to_coords("(0 0), (60 50)").unwrap();
+            FanCurve::Boost(BoostCurve::new(graph, GraphType::Linear))
+        };
+        controller.add_profile(String::from("silent"), [silent(), silent()]);
+        controller
+    }
+
+    /// Runs the watch until `commands` are all handled
+    fn run(controller: &mut Controller, commands: Vec<ControlCommand>) {
+        drive(controller, |handle| {
+            for command in commands {
+                handle.send(command).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn a_restarted_controller_resumes_profile_and_overrides() {
+        with_simulated(|sim| {
+            let path = env::temp_dir().join(format!("awc-{}-state.json", process::id()));
+            let _ = fs::remove_file(&path);
+
+            let mut first = controller();
+            first.restore_state(path.clone());
+            run(
+                &mut first,
+                vec![
+                    ControlCommand::SetProfile(String::from("silent")),
+                    ControlCommand::SetBoost {
+                        fan_id: 51,
+                        boost: 180,
+                        duration: None,
+                    },
+                ],
+            );
+            let saved = load(&path).unwrap();
+            assert_eq!(saved.profile, "silent");
+            assert_eq!(saved.pinned[&51].boost, 180);
+            // a clean shutdown already put the power mode back
+            assert_eq!(saved.original_power_mode, None);
+
+            sim.set_temp(1, 60);
+            let mut second = controller();
+            second.restore_state(path.clone());
+            let status = drive(&mut second, |handle| {
+                handle.send(ControlCommand::Status).unwrap().to_string()
+            });
+            assert!(status.contains("Profile: silent"));
+            assert!(status.contains("boost: 50/255"));
+            assert!(status.contains("pinned: 180"));
+            assert!(status.contains("Watchdog fired: "));
+            fs::remove_file(path).unwrap();
+        });
+    }
+
+    #[test]
+    fn rpm_curves_carry_on_from_the_saved_boosts() {
+        with_simulated(|sim| {
+            let path = env::temp_dir().join(format!("awc-{}-boosts.json", process::id()));
+            let _ = fs::remove_file(&path);
+            let resumed = State {
+                profile: String::from("default"),
+                boosts: BTreeMap::from([(50, 140), (51, 60)]),
+                ..State::default()
+            };
+            save(&path, &resumed).unwrap();
+
+            let (cpu, gpu) =
+                get_rpm_coords_from_string("(0 0), (60 3000)\n(0 0), (60 3000)").unwrap();
+            let rpm = |graph| FanCurve::Rpm(RpmTarget::new(graph, GraphType::Linear, None, 100));
+            let mut controller = Controller::new(
+                ALIEN_DEVICES,
+                [rpm(cpu), rpm(gpu)],
+                ThermalEmergency::new(vec![], Duration::ZERO),
+            );
+            controller.restore_state(path.clone());
+            // the first tick, with the fans still at the firmware's 0
+            let first = drive(&mut controller, |handle| {
+                handle.send(ControlCommand::Status).unwrap();
+                (sim.boost(50), sim.boost(51))
+            });
+
+            assert_eq!(first, (140, 60));
+            assert_eq!(load(&path).unwrap().boosts[&50], 140);
+            fs::remove_file(path).unwrap();
+        });
+    }
+
+    #[test]
+    fn after_a_crash_the_recorded_power_mode_is_restored() {
+        with_simulated(|sim| {
+            let path = env::temp_dir().join(format!("awc-{}-crashed.json", process::id()));
+            let crashed = State {
+                profile: String::from("default"),
+                original_power_mode: Some(0xab),
+                ..State::default()
+            };
+            save(&path, &crashed).unwrap();
+            sim.set_power_mode(0);
+
+            let mut controller = controller();
+            controller.restore_state(path.clone());
+            run(&mut controller, vec![]);
+
+            assert_eq!(sim.power_mode(), 0xab);
+            assert_eq!(load(&path).unwrap().original_power_mode, None);
+            fs::remove_file(path).unwrap();
+        });
+    }
+}
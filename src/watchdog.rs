@@ -0,0 +1,180 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use clap::ValueEnum;
+
+use crate::controller::{get_fan_rpm, set_fan_boost};
+
+/// How many times the watchdog fired, per fan id
+static FIRED: Mutex<BTreeMap<u8, u64>> = Mutex::new(BTreeMap::new());
+
+/// What the watchdog does to a fan that is stuck at the same rpm
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum RecoveryAction {
+    /// Briefly drop the boost to 0, then restore it
+    PulseZero,
+    /// Briefly raise the boost to 255, then restore it
+    PulseMax,
+    /// Write the current boost again
+    Reapply,
+}
+
+#[derive(Debug)]
+pub struct LastFanRPMRecorded {
+    pub rpm: i64,
+    pub ts: SystemTime,
+}
+
+impl LastFanRPMRecorded {
+    pub fn new(rpm: i64) -> Self {
+        Self {
+            rpm,
+            ts: SystemTime::now(),
+        }
+    }
+}
+
+/// Some firmwares leave a fan stuck at the same rpm and ignore new boosts
+/// until the boost is nudged. The watchdog notices fans whose rpm hasn't
+/// moved for `timeout` and kicks them with `action`.
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    pub timeout: Duration,
+    pub rpm_tolerance: u32,
+    pub action: RecoveryAction,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, rpm_tolerance: u32, action: RecoveryAction) -> Self {
+        Self {
+            timeout,
+            rpm_tolerance,
+            action,
+        }
+    }
+
+    /// Returns true if the fan was stuck and got recovered
+    pub fn check(&self, fan_id: u8, last: &mut LastFanRPMRecorded, rpm: i64, boost: u8) -> bool {
+        if (rpm - last.rpm).unsigned_abs() > self.rpm_tolerance as u64 {
+            *last = LastFanRPMRecorded::new(rpm);
+            return false;
+        }
+        // an idle fan is not a stuck fan
+        if rpm == 0 && boost == 0 {
+            return false;
+        }
+        if last.ts.elapsed().unwrap_or_default() <= self.timeout {
+            return false;
+        }
+
+        self.recover(fan_id, boost);
+        *FIRED.lock().unwrap().entry(fan_id).or_default() += 1;
+        *last = LastFanRPMRecorded::new(get_fan_rpm(fan_id));
+        true
+    }
+
+    fn recover(&self, fan_id: u8, boost: u8) {
+        let pulse = match self.action {
+            RecoveryAction::PulseZero => Some(0),
+            RecoveryAction::PulseMax => Some(255),
+            RecoveryAction::Reapply => None,
+        };
+        if let Some(pulse) = pulse {
+            let result = set_fan_boost(fan_id, pulse);
+            warn!(fan_id = fan_id, result = result; "Fan #{fan_id} stuck, pulsed boost to {pulse} result: {result}");
+            thread::sleep(Duration::from_millis(200));
+        }
+        let result = set_fan_boost(fan_id, boost);
+        warn!(fan_id = fan_id, result = result; "Fan #{fan_id} stuck, reapplied boost {boost} result: {result}");
+    }
+}
+
+pub fn fired_count(fan_id: u8) -> u64 {
+    FIRED
+        .lock()
+        .unwrap()
+        .get(&fan_id)
+        .copied()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        control::{ControlCommand, Reply},
+        controller::get_fan_boost,
+        simulated::{drive_with, test_controller, with_simulated},
+    };
+
+    /// Last seen at `rpm` a second ago, so a zero timeout has run out
+    fn stale(rpm: i64) -> LastFanRPMRecorded {
+        LastFanRPMRecorded {
+            rpm,
+            ts: SystemTime::now() - Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn rpms_within_the_tolerance_count_as_stuck() {
+        with_simulated(|_| {
+            let watchdog = Watchdog::new(Duration::ZERO, 50, RecoveryAction::Reapply);
+            let mut last = stale(1000);
+            assert!(watchdog.check(50, &mut last, 1040, 50));
+
+            let mut last = stale(1000);
+            assert!(!watchdog.check(50, &mut last, 1100, 50));
+            assert_eq!(last.rpm, 1100);
+        });
+    }
+
+    #[test]
+    fn idle_fans_and_fans_within_the_timeout_are_left_alone() {
+        with_simulated(|_| {
+            let watchdog = Watchdog::new(Duration::ZERO, 0, RecoveryAction::Reapply);
+            assert!(!watchdog.check(50, &mut stale(0), 0, 0));
+
+            let patient = Watchdog::new(Duration::from_secs(3600), 0, RecoveryAction::Reapply);
+            assert!(!patient.check(50, &mut stale(1000), 1000, 50));
+        });
+    }
+
+    #[test]
+    fn each_action_ends_back_at_the_boost() {
+        for (action, writes) in [
+            (RecoveryAction::PulseZero, vec![0, 120]),
+            (RecoveryAction::PulseMax, vec![255, 120]),
+            (RecoveryAction::Reapply, vec![120]),
+        ] {
+            with_simulated(|sim| {
+                let watchdog = Watchdog::new(Duration::ZERO, 0, action);
+                assert!(watchdog.check(50, &mut stale(1000), 1000, 120));
+                assert_eq!(sim.written(50), writes);
+                assert_eq!(get_fan_boost(50), 120);
+            });
+        }
+    }
+
+    #[test]
+    fn firings_are_counted_in_the_status() {
+        with_simulated(|sim| {
+            sim.set_stuck_rpm(50, Some(1000));
+            let before = fired_count(50);
+            let watchdog = Watchdog::new(Duration::ZERO, 0, RecoveryAction::Reapply);
+
+            let status = drive_with(&mut test_controller(), &watchdog, |handle| {
+                let Ok(Reply::Status(status)) = handle.send(ControlCommand::Status) else {
+                    panic!("no status");
+                };
+                status
+            });
+
+            assert_eq!(status.fans[0].watchdog_fired, before + 1);
+            assert_eq!(status.fans[1].watchdog_fired, fired_count(51));
+        });
+    }
+}